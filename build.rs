@@ -0,0 +1,40 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// Bakes the git SHA into the binary for the `build_info` metric. `GIT_SHA` can
+// be passed in at build time (e.g. from CI or `docker build --build-arg`),
+// otherwise we ask git, falling back to "unknown" outside a checkout.
+fn main() {
+    let git_sha = env::var("GIT_SHA")
+        .ok()
+        .filter(|sha| !sha.is_empty())
+        .or_else(|| {
+            Command::new("git")
+                .args(["rev-parse", "--short", "HEAD"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=GIT_SHA={}", git_sha);
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    // HEAD itself only changes when switching branches, a new commit moves
+    // the branch it points to instead
+    if let Ok(head) = fs::read_to_string(".git/HEAD") {
+        if let Some(branch) = head.trim().strip_prefix("ref: ") {
+            let loose_ref = Path::new(".git").join(branch);
+            if loose_ref.exists() {
+                println!("cargo:rerun-if-changed={}", loose_ref.display());
+            }
+        }
+    }
+    // Where the branch ends up after `git gc` packs its ref
+    if Path::new(".git/packed-refs").exists() {
+        println!("cargo:rerun-if-changed=.git/packed-refs");
+    }
+}
@@ -1,40 +1,135 @@
-use serde::{Deserialize, Serialize};
+use anyhow::Context;
+use chrono::{DateTime, Datelike, Months, NaiveDate, TimeZone, Utc};
+use log::{debug, error, info, warn};
+use prometheus_exporter::prometheus::core::Collector;
+use prometheus_exporter::prometheus::proto::MetricFamily;
+use prometheus_exporter::prometheus::{
+    exponential_buckets, register_gauge_vec_with_registry, register_gauge_with_registry,
+    register_histogram_vec_with_registry, register_histogram_with_registry,
+    register_int_counter_vec_with_registry, register_int_counter_with_registry,
+    register_int_gauge_vec_with_registry, register_int_gauge_with_registry, Encoder, Gauge,
+    GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use reqwest::{
+    header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER},
+    Client, RequestBuilder, Response, StatusCode, Url,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Comment {
     count: i32,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The ledger transaction behind a transfer, from `expand=transaction`
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
-    id: String,
+    pub id: String,
     object: String,
     href: String,
-    amount_cents: u64,
-    memo: String,
-    date: String,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub amount_cents: i64,
+    pub memo: String,
+    #[serde(default)]
+    pub date: Option<String>,
     #[serde(rename = "type")]
-    type_: String,
-    pending: bool,
+    pub type_: String,
+    #[serde(default)]
+    pub pending: Option<bool>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Organization {
-    id: String,
+    pub id: String,
     object: String,
     href: String,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+/// Only the id is needed, so recipients with other fields missing still parse
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recipient {
+    pub id: String,
+}
+
+/// A transfer out of an HCB organization, as listed by
+/// `/organizations/{org}/transfers`. Fields HCB doesn't always send are `Option`s.
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Transfer {
-    id: String,
+    /// Stable across pages, used to deduplicate
+    pub id: String,
     object: String,
     href: String,
-    transaction: Transaction,
-    organization: Organization,
+    pub transaction: Transaction,
+    #[serde(default)]
+    pub organization: Option<Organization>,
+    /// The organization the money went to, when HCB includes it
+    #[serde(default, rename = "to")]
+    pub recipient: Option<Recipient>,
+    #[serde(deserialize_with = "deserialize_amount")]
     pub amount_cents: u64,
-    date: String,
-    status: String,
+    /// The memo entered on the transfer itself, categories still go by the
+    /// transaction's memo
+    #[serde(default)]
+    pub memo: Option<String>,
+    /// `None` when HCB leaves the date out or sends something unparseable
+    #[serde(default, deserialize_with = "deserialize_date")]
+    pub date: Option<DateTime<Utc>>,
+    /// e.g. "completed" or "pending"
+    #[serde(default)]
+    pub status: Option<String>,
+}
+
+/// Parses HCB dates, which come as either RFC 3339 timestamps or bare dates
+pub fn parse_date(raw: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(raw)
+        .map(|date| date.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(raw, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .map(|date| Utc.from_utc_datetime(&date))
+        })
+}
+
+fn deserialize_date<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw: Option<String> = Option::deserialize(deserializer)?;
+    Ok(raw.as_deref().and_then(parse_date))
+}
+
+/// HCB has sent amounts both as JSON numbers and as strings of digits,
+/// depending on the endpoint, so either is accepted
+fn deserialize_amount<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr,
+    T::Err: fmt::Display,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Amount<T> {
+        Number(T),
+        Text(String),
+    }
+
+    match Amount::<T>::deserialize(deserializer)? {
+        Amount::Number(amount) => Ok(amount),
+        Amount::Text(raw) => raw
+            .trim()
+            .parse()
+            .map_err(|e| serde::de::Error::custom(format!("invalid amount \"{}\": {}", raw, e))),
+    }
 }
 
 #[derive(Deserialize, Clone)]
@@ -62,9 +157,1113 @@ pub struct Label {
     pub name: String,
 }
 
-pub enum AirTableViews {
-    Pending,
-    Approved,
+/// One page of the Airtable list records endpoint
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AirtableResponse {
+    pub records: Vec<AirtableRecord>,
+    /// Set while there are more pages, pass it back to get the next one
+    #[serde(default)]
+    pub offset: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AirtableRecord {
+    pub id: String,
+    #[serde(rename = "createdTime")]
+    pub created_time: String,
+    /// Only the requested fields, and Airtable leaves out empty ones
+    #[serde(default)]
+    pub fields: HashMap<String, serde_json::Value>,
+}
+
+/// The `project.yml` some project folders carry, e.g. `requested_dollars: 50`.
+/// Fields other than the ones below are ignored.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProjectManifest {
+    /// Dollars the project asked for
+    pub requested_dollars: f64,
+}
+
+pub struct RepoCache {
+    pub last_fetch: Instant,
+    pub path: PathBuf,
+}
+
+/// Where and how `fetch_hcb` pages through transfers
+pub struct HcbConfig {
+    pub api_url: String,
+    pub orgs: Vec<String>,
+    /// Transfers above this many dollars aren't counted as grants
+    pub max_grant_dollars: u64,
+    pub page_size: u32,
+    /// Transaction types counted as grants, every type when empty
+    pub include_types: Vec<String>,
+    /// Transaction types never counted as grants, e.g. fees or reversals
+    pub exclude_types: Vec<String>,
+    pub cache_ttl: Duration,
+    pub max_retries: u32,
+    /// Only keep running totals per org instead of every transfer
+    pub streaming: bool,
+    /// Log transfers that fail to parse, see `dump_json`
+    pub dump_json: bool,
+}
+
+/// Sent with every outbound request, some APIs reject the default one
+pub const USER_AGENT: &str = concat!("onboard-grant-aggregator/", env!("CARGO_PKG_VERSION"));
+
+/// HCB won't return more than this many transfers per page
+pub const HCB_MAX_PAGE_SIZE: u32 = 100;
+
+/// An org's transfers as of `last_fetch`, reused until they're older than the TTL
+pub struct HcbCache {
+    pub last_fetch: Instant,
+    pub transfers: Vec<Transfer>,
+}
+
+/// Gauges `fetch_hcb` maintains itself while paging through an org's transfers
+pub struct HcbMetrics {
+    pub parse_errors: IntGaugeVec,
+    pub pages_fetched: IntGaugeVec,
+    /// Transfers HCB returned before any filtering
+    pub raw_transfers: IntGaugeVec,
+    /// Transfers dropped by the type filter and the grant ceiling, by `reason`
+    pub filtered_out: IntGaugeVec,
+    pub response_bytes: HistogramVec,
+}
+
+/// Counters the Airtable fetches maintain themselves
+pub struct AirtableMetrics {
+    /// Bumped on every 429, including the ones a retry recovers from
+    pub rate_limited: IntCounter,
+    /// Bumped on every 401 or 403, i.e. a missing or wrong AIRTABLE_API key
+    pub auth_errors: IntCounter,
+    pub views_configured: IntGauge,
+    /// Views counted without an error in the last refresh
+    pub views_fetched: IntGauge,
+}
+
+/// Counters `count_dirs` bumps around its clones of the OnBoard repo
+#[derive(Clone)]
+pub struct GitMetrics {
+    pub clones: IntCounter,
+    pub clone_failures: IntCounter,
+}
+
+/// Handles to every metric the exporter maintains, see `register_metrics`
+pub struct Metrics {
+    pub build_info: IntGaugeVec,
+    pub refresh_interval_seconds: Gauge,
+    pub pr_reviewer_stats: IntGaugeVec,
+    pub pr_reviewer_stats_merged: IntGaugeVec,
+    pub pr_merger_stats: GaugeVec,
+    pub pr_review_stats: GaugeVec,
+    pub submitted_projects: GaugeVec,
+    pub total_submitted_projects: Gauge,
+    pub submitted_projects_stale: IntGaugeVec,
+    pub submitted_projects_delta: Gauge,
+    pub submitted_projects_by_category: GaugeVec,
+    pub submitted_projects_by_readme: GaugeVec,
+    pub projects_requested_dollars_total: Gauge,
+    pub projects_missing_manifest: IntGauge,
+    pub git: GitMetrics,
+    pub transfers_count: IntGaugeVec,
+    pub transfers_count_by_month: IntGaugeVec,
+    pub avg_grant: GaugeVec,
+    pub avg_grant_smoothed: GaugeVec,
+    pub median_grant: GaugeVec,
+    pub max_grant: GaugeVec,
+    pub grant_range_dollars: GaugeVec,
+    pub grants_per_day: GaugeVec,
+    pub total_grants_dollars: GaugeVec,
+    pub avg_grant_stale: IntGaugeVec,
+    pub median_grant_stale: IntGaugeVec,
+    pub max_grant_stale: IntGaugeVec,
+    pub total_grants_dollars_stale: IntGaugeVec,
+    pub oldest_transfer_timestamp: GaugeVec,
+    pub newest_transfer_timestamp: GaugeVec,
+    pub distinct_recipients: IntGaugeVec,
+    pub distinct_programs: IntGaugeVec,
+    pub transfers_unknown_program: IntGaugeVec,
+    pub transfers_by_category: IntGaugeVec,
+    pub grant_amount_dollars: Histogram,
+    pub transfers_total: IntCounterVec,
+    pub airtable_records: IntGaugeVec,
+    pub airtable_records_stale: IntGaugeVec,
+    pub airtable: AirtableMetrics,
+    pub airtable_approval_seconds: GaugeVec,
+    pub airtable_approval_ratio: Gauge,
+    pub waiting_review: IntGauge,
+    pub waiting_review_stale: IntGauge,
+    pub onboard_prs: IntGaugeVec,
+    pub onboard_prs_stale: IntGaugeVec,
+    pub hcb: HcbMetrics,
+    pub github_rate_limit_remaining: IntGaugeVec,
+    pub scrape_duration_seconds: HistogramVec,
+    pub scrape_errors_total: IntCounterVec,
+    pub last_successful_scrape_timestamp: GaugeVec,
+    pub consecutive_scrape_failures: IntGaugeVec,
+    pub upstreams_reachable: IntGauge,
+    pub circuit_open: IntGaugeVec,
+}
+
+/// Label names the metrics already vary by, which a static label can't reuse
+pub const VARIABLE_LABEL_NAMES: &[&str] = &[
+    "version",
+    "git_sha",
+    "reviewer",
+    "category",
+    "has_readme",
+    "org",
+    "period",
+    "month",
+    "status",
+    "filter",
+    "stat",
+    "state",
+    "reason",
+    "api",
+    "source",
+    "repo",
+    "le",
+];
+
+/// Default upper bounds of `grant_amount_dollars`, override with GRANT_HISTOGRAM_BUCKETS
+pub const DEFAULT_GRANT_BUCKETS: &[f64] = &[5.0, 10.0, 25.0, 50.0, 75.0, 100.0];
+
+/// Registers every metric in `registry`, which is the global default registry
+/// for the binary but can be any registry a host service already exports.
+/// `static_labels` are attached to every series; their names must not be in
+/// `VARIABLE_LABEL_NAMES`. `grant_buckets` are the upper bounds, in dollars,
+/// of `grant_amount_dollars`.
+pub fn register_metrics(
+    registry: &Registry,
+    static_labels: &HashMap<String, String>,
+    grant_buckets: &[f64],
+) -> Metrics {
+    let opts = |name: &str, help: &str| Opts::new(name, help).const_labels(static_labels.clone());
+    let histogram_opts = |name: &str, help: &str, buckets: Vec<f64>| {
+        HistogramOpts::new(name, help)
+            .const_labels(static_labels.clone())
+            .buckets(buckets)
+    };
+
+    Metrics {
+        build_info: register_int_gauge_vec_with_registry!(
+            opts("build_info", "Version and git SHA of the running exporter, always 1"),
+            &["version", "git_sha"],
+            registry
+        )
+        .expect("Cannot create gauge vector build_info"),
+        refresh_interval_seconds: register_gauge_with_registry!(
+            opts(
+                "refresh_interval_seconds",
+                "Configured time between refreshes of the upstream data",
+            ),
+            registry
+        )
+        .expect("Cannot create gauge refresh_interval_seconds"),
+        pr_reviewer_stats: register_int_gauge_vec_with_registry!(
+            opts("pr_reviewer_stats", "Number of pull requests reviewed by each reviewer"),
+            &["reviewer"],
+            registry
+        )
+        .expect("Failed to create counter vector"),
+        pr_reviewer_stats_merged: register_int_gauge_vec_with_registry!(
+            opts(
+                "pr_reviewer_stats_merged",
+                "Number of pull requests merged reviewed by each reviewer",
+            ),
+            &["reviewer"],
+            registry
+        )
+        .expect("Failed to create counter vector"),
+        pr_merger_stats: register_gauge_vec_with_registry!(
+            opts("pr_merger_stats", "Number of pull requests merged by each reviewer"),
+            &["reviewer"],
+            registry
+        )
+        .expect("Failed to create gauge vector pr_merger_stats"),
+        pr_review_stats: register_gauge_vec_with_registry!(
+            opts(
+                "pr_review_stats",
+                "Number of reviews submitted on merged pull requests by each reviewer",
+            ),
+            &["reviewer"],
+            registry
+        )
+        .expect("Failed to create gauge vector pr_review_stats"),
+        submitted_projects: register_gauge_vec_with_registry!(
+            opts(
+                "submitted_projects",
+                "Number of project folders in each tracked repo",
+            ),
+            &["repo"],
+            registry
+        )
+        .expect("Cannot create gauge vector submitted_projects"),
+        total_submitted_projects: register_gauge_with_registry!(
+            opts(
+                "total_submitted_projects",
+                "Number of project folders across every tracked repo",
+            ),
+            registry
+        )
+        .expect("Cannot create gauge total_submitted_projects"),
+        submitted_projects_stale: register_int_gauge_vec_with_registry!(
+            opts(
+                "submitted_projects_stale",
+                "1 while submitted_projects shows a repo's last good count after a failed clone",
+            ),
+            &["repo"],
+            registry
+        )
+        .expect("Cannot create gauge vector submitted_projects_stale"),
+        submitted_projects_delta: register_gauge_with_registry!(
+            opts(
+                "submitted_projects_delta",
+                "Change in submitted_projects since the previous refresh, 0 on the first",
+            ),
+            registry
+        )
+        .expect("Cannot create gauge submitted_projects_delta"),
+        submitted_projects_by_category: register_gauge_vec_with_registry!(
+            opts(
+                "submitted_projects_by_category",
+                "Number of project folders in each category folder of the projects directory",
+            ),
+            &["category"],
+            registry
+        )
+        .expect("Cannot create gauge vector submitted_projects_by_category"),
+        submitted_projects_by_readme: register_gauge_vec_with_registry!(
+            opts(
+                "submitted_projects_by_readme",
+                "Number of project folders with and without a README, unknown if unreadable",
+            ),
+            &["has_readme"],
+            registry
+        )
+        .expect("Cannot create gauge vector submitted_projects_by_readme"),
+        projects_requested_dollars_total: register_gauge_with_registry!(
+            opts(
+                "projects_requested_dollars_total",
+                "Dollars requested across the project.yml manifests of all projects",
+            ),
+            registry
+        )
+        .expect("Cannot create gauge projects_requested_dollars_total"),
+        projects_missing_manifest: register_int_gauge_with_registry!(
+            opts(
+                "projects_missing_manifest",
+                "Number of project folders without a project.yml that parses",
+            ),
+            registry
+        )
+        .expect("Cannot create gauge projects_missing_manifest"),
+        git: GitMetrics {
+            clones: register_int_counter_with_registry!(
+                opts("git_clones_total", "Clones of the OnBoard repo, one per cache miss"),
+                registry
+            )
+            .expect("Cannot create counter git_clones_total"),
+            clone_failures: register_int_counter_with_registry!(
+                opts(
+                    "git_clone_failures_total",
+                    "Failed OnBoard repo clones, including ones that fell back to the last copy",
+                ),
+                registry
+            )
+            .expect("Cannot create counter git_clone_failures_total"),
+        },
+        transfers_count: register_int_gauge_vec_with_registry!(
+            opts(
+                "transfers_count",
+                "Grant transfers out of each Hack Club Bank organization over each period",
+            ),
+            &["org", "period"],
+            registry
+        )
+        .expect("Cannot create gauge vector transfers_count"),
+        transfers_count_by_month: register_int_gauge_vec_with_registry!(
+            opts(
+                "transfers_count_by_month",
+                "Grant transfers out of each HCB organization in each of the last 12 months",
+            ),
+            &["org", "month"],
+            registry
+        )
+        .expect("Cannot create gauge vector transfers_count_by_month"),
+        avg_grant: register_gauge_vec_with_registry!(
+            opts(
+                "avg_grant",
+                "Average dollars given per grant by each Hack Club Bank organization",
+            ),
+            &["org"],
+            registry
+        )
+        .expect("Cannot create gauge vector average_grant_value"),
+        avg_grant_smoothed: register_gauge_vec_with_registry!(
+            opts("avg_grant_smoothed", "Exponential moving average of avg_grant across refreshes"),
+            &["org"],
+            registry
+        )
+        .expect("Cannot create gauge vector avg_grant_smoothed"),
+        median_grant: register_gauge_vec_with_registry!(
+            opts(
+                "median_grant",
+                "Median dollars given per grant by each Hack Club Bank organization",
+            ),
+            &["org"],
+            registry
+        )
+        .expect("Cannot create gauge vector median_grant"),
+        max_grant: register_gauge_vec_with_registry!(
+            opts("max_grant", "Largest grant in dollars given by each Hack Club Bank organization"),
+            &["org"],
+            registry
+        )
+        .expect("Cannot create gauge vector max_grant"),
+        grant_range_dollars: register_gauge_vec_with_registry!(
+            opts(
+                "grant_range_dollars",
+                "Largest minus smallest grant in dollars of each Hack Club Bank organization",
+            ),
+            &["org"],
+            registry
+        )
+        .expect("Cannot create gauge vector grant_range_dollars"),
+        grants_per_day: register_gauge_vec_with_registry!(
+            opts(
+                "grants_per_day",
+                "Grants per day over the trailing GRANTS_PER_DAY_WINDOW_DAYS of each organization",
+            ),
+            &["org"],
+            registry
+        )
+        .expect("Cannot create gauge vector grants_per_day"),
+        total_grants_dollars: register_gauge_vec_with_registry!(
+            opts(
+                "total_grants_dollars",
+                "Total dollars given in grants by each Hack Club Bank organization",
+            ),
+            &["org"],
+            registry
+        )
+        .expect("Cannot create gauge vector total_grants_dollars"),
+        avg_grant_stale: register_int_gauge_vec_with_registry!(
+            opts(
+                "avg_grant_stale",
+                "1 while avg_grant shows its last good value after a failed HCB fetch",
+            ),
+            &["org"],
+            registry
+        )
+        .expect("Cannot create gauge vector avg_grant_stale"),
+        median_grant_stale: register_int_gauge_vec_with_registry!(
+            opts(
+                "median_grant_stale",
+                "1 while median_grant shows its last good value after a failed HCB fetch",
+            ),
+            &["org"],
+            registry
+        )
+        .expect("Cannot create gauge vector median_grant_stale"),
+        max_grant_stale: register_int_gauge_vec_with_registry!(
+            opts(
+                "max_grant_stale",
+                "1 while max_grant shows its last good value after a failed HCB fetch",
+            ),
+            &["org"],
+            registry
+        )
+        .expect("Cannot create gauge vector max_grant_stale"),
+        total_grants_dollars_stale: register_int_gauge_vec_with_registry!(
+            opts(
+                "total_grants_dollars_stale",
+                "1 while total_grants_dollars shows its last good value after a failed HCB fetch",
+            ),
+            &["org"],
+            registry
+        )
+        .expect("Cannot create gauge vector total_grants_dollars_stale"),
+        oldest_transfer_timestamp: register_gauge_vec_with_registry!(
+            opts(
+                "oldest_transfer_timestamp",
+                "Unix timestamp of the oldest dated transfer of each Hack Club Bank organization",
+            ),
+            &["org"],
+            registry
+        )
+        .expect("Cannot create gauge vector oldest_transfer_timestamp"),
+        newest_transfer_timestamp: register_gauge_vec_with_registry!(
+            opts(
+                "newest_transfer_timestamp",
+                "Unix timestamp of the newest dated transfer of each Hack Club Bank organization",
+            ),
+            &["org"],
+            registry
+        )
+        .expect("Cannot create gauge vector newest_transfer_timestamp"),
+        distinct_recipients: register_int_gauge_vec_with_registry!(
+            opts(
+                "distinct_recipients",
+                "Distinct organizations that received grants from each Hack Club Bank organization",
+            ),
+            &["org"],
+            registry
+        )
+        .expect("Cannot create gauge vector distinct_recipients"),
+        distinct_programs: register_int_gauge_vec_with_registry!(
+            opts(
+                "distinct_programs",
+                "Distinct programs tagged as [program] in the memos of each organization's grants",
+            ),
+            &["org"],
+            registry
+        )
+        .expect("Cannot create gauge vector distinct_programs"),
+        transfers_unknown_program: register_int_gauge_vec_with_registry!(
+            opts(
+                "transfers_unknown_program",
+                "Grants whose memo has no [program] tag, left out of distinct_programs",
+            ),
+            &["org"],
+            registry
+        )
+        .expect("Cannot create gauge vector transfers_unknown_program"),
+        transfers_by_category: register_int_gauge_vec_with_registry!(
+            opts(
+                "transfers_by_category",
+                "Grant transfers out of each Hack Club Bank organization by memo category",
+            ),
+            &["org", "category"],
+            registry
+        )
+        .expect("Cannot create gauge vector transfers_by_category"),
+        grant_amount_dollars: register_histogram_with_registry!(
+            histogram_opts(
+                "grant_amount_dollars",
+                "Distribution of dollars given per grant",
+                grant_buckets.to_vec(),
+            ),
+            registry
+        )
+        .expect("Cannot create histogram grant_amount_dollars"),
+        transfers_total: register_int_counter_vec_with_registry!(
+            opts(
+                "transfers_total",
+                "Grant transfers seen out of each Hack Club Bank organization since startup",
+            ),
+            &["org"],
+            registry
+        )
+        .expect("Cannot create counter vector transfers_total"),
+        airtable_records: register_int_gauge_vec_with_registry!(
+            opts(
+                "airtable_records",
+                "Number of Airtable records in each configured view, or matching AIRTABLE_FILTER_FORMULA",
+            ),
+            &["status", "filter"],
+            registry
+        )
+        .expect("Cannot create gauge vector airtable_records"),
+        airtable_records_stale: register_int_gauge_vec_with_registry!(
+            opts(
+                "airtable_records_stale",
+                "1 while airtable_records shows its last good count after a failed Airtable fetch",
+            ),
+            &["status", "filter"],
+            registry
+        )
+        .expect("Cannot create gauge vector airtable_records_stale"),
+        airtable: AirtableMetrics {
+            rate_limited: register_int_counter_with_registry!(
+                opts(
+                    "airtable_rate_limited_total",
+                    "Number of Airtable requests answered with 429 Too Many Requests",
+                ),
+                registry
+            )
+            .expect("Cannot create counter airtable_rate_limited_total"),
+            auth_errors: register_int_counter_with_registry!(
+                opts(
+                    "airtable_auth_errors_total",
+                    "Number of Airtable requests refused with 401 Unauthorized or 403 Forbidden",
+                ),
+                registry
+            )
+            .expect("Cannot create counter airtable_auth_errors_total"),
+            views_configured: register_int_gauge_with_registry!(
+                opts(
+                    "airtable_views_configured",
+                    "Number of Airtable views configured to be counted",
+                ),
+                registry
+            )
+            .expect("Cannot create gauge airtable_views_configured"),
+            views_fetched: register_int_gauge_with_registry!(
+                opts(
+                    "airtable_views_fetched",
+                    "Number of configured Airtable views counted successfully in the last refresh",
+                ),
+                registry
+            )
+            .expect("Cannot create gauge airtable_views_fetched"),
+        },
+        airtable_approval_seconds: register_gauge_vec_with_registry!(
+            opts(
+                "airtable_approval_seconds",
+                "Time from submission to approval of the approved Airtable records",
+            ),
+            &["stat"],
+            registry
+        )
+        .expect("Cannot create gauge vector airtable_approval_seconds"),
+        airtable_approval_ratio: register_gauge_with_registry!(
+            opts(
+                "airtable_approval_ratio",
+                "Approved Airtable records over approved plus pending, 0 when both are 0",
+            ),
+            registry
+        )
+        .expect("Cannot create gauge airtable_approval_ratio"),
+        waiting_review: register_int_gauge_with_registry!(
+            opts("waiting_review", "Number of Pull Requests waiting a review"),
+            registry
+        )
+        .expect("Cannot create gauge airtable_records_pending_metric"),
+        waiting_review_stale: register_int_gauge_with_registry!(
+            opts(
+                "waiting_review_stale",
+                "1 while waiting_review shows its last good count after a failed GitHub fetch",
+            ),
+            registry
+        )
+        .expect("Cannot create gauge waiting_review_stale"),
+        onboard_prs: register_int_gauge_vec_with_registry!(
+            opts(
+                "onboard_prs",
+                "Open or merged OnBoard pull requests, limited to GITHUB_PR_LABEL when set",
+            ),
+            &["state"],
+            registry
+        )
+        .expect("Cannot create gauge vector onboard_prs"),
+        onboard_prs_stale: register_int_gauge_vec_with_registry!(
+            opts(
+                "onboard_prs_stale",
+                "1 while onboard_prs shows its last good count after a failed GitHub fetch",
+            ),
+            &["state"],
+            registry
+        )
+        .expect("Cannot create gauge vector onboard_prs_stale"),
+        hcb: HcbMetrics {
+            parse_errors: register_int_gauge_vec_with_registry!(
+                opts(
+                    "hcb_transfers_parse_errors",
+                    "Number of HCB transfers skipped because they failed to deserialize",
+                ),
+                &["org"],
+                registry
+            )
+            .expect("Cannot create gauge vector hcb_transfers_parse_errors"),
+            pages_fetched: register_int_gauge_vec_with_registry!(
+                opts(
+                    "hcb_pages_fetched",
+                    "Number of HCB transfer pages walked during the last refresh",
+                ),
+                &["org"],
+                registry
+            )
+            .expect("Cannot create gauge vector hcb_pages_fetched"),
+            raw_transfers: register_int_gauge_vec_with_registry!(
+                opts(
+                    "hcb_transfers_raw_count",
+                    "Transfers HCB returned for each organization before type and amount filtering",
+                ),
+                &["org"],
+                registry
+            )
+            .expect("Cannot create gauge vector hcb_transfers_raw_count"),
+            filtered_out: register_int_gauge_vec_with_registry!(
+                opts(
+                    "transfers_filtered_out",
+                    "Transfers not counted as grants, by type filter or MAX_GRANT_DOLLARS ceiling",
+                ),
+                &["org", "reason"],
+                registry
+            )
+            .expect("Cannot create gauge vector transfers_filtered_out"),
+            // 256 B error bodies up to 16 MiB pages
+            response_bytes: register_histogram_vec_with_registry!(
+                histogram_opts(
+                    "hcb_response_bytes",
+                    "Size of each HCB transfers page response body",
+                    exponential_buckets(256.0, 4.0, 9).expect("Invalid hcb_response_bytes buckets")
+                ),
+                &["org"],
+                registry
+            )
+            .expect("Cannot create histogram vector hcb_response_bytes"),
+        },
+        // Only gets a series once an authenticated call has reported its limit
+        github_rate_limit_remaining: register_int_gauge_vec_with_registry!(
+            opts(
+                "github_rate_limit_remaining",
+                "Requests left in the current GitHub API rate limit window",
+            ),
+            &["api"],
+            registry
+        )
+        .expect("Cannot create gauge vector github_rate_limit_remaining"),
+        scrape_duration_seconds: register_histogram_vec_with_registry!(
+            histogram_opts(
+                "scrape_duration_seconds",
+                "Time spent fetching from each upstream data source",
+                vec![0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0],
+            ),
+            &["source"],
+            registry
+        )
+        .expect("Cannot create histogram vector scrape_duration_seconds"),
+        scrape_errors_total: register_int_counter_vec_with_registry!(
+            opts("scrape_errors_total", "Number of failed fetches from each upstream data source"),
+            &["source"],
+            registry
+        )
+        .expect("Failed to create counter vector scrape_errors_total"),
+        last_successful_scrape_timestamp: register_gauge_vec_with_registry!(
+            opts(
+                "last_successful_scrape_timestamp",
+                "Unix timestamp of the last successful fetch from each upstream data source",
+            ),
+            &["source"],
+            registry
+        )
+        .expect("Failed to create gauge vector last_successful_scrape_timestamp"),
+        consecutive_scrape_failures: register_int_gauge_vec_with_registry!(
+            opts(
+                "consecutive_scrape_failures",
+                "Number of refresh cycles in a row each upstream data source has failed",
+            ),
+            &["source"],
+            registry
+        )
+        .expect("Failed to create gauge vector consecutive_scrape_failures"),
+        upstreams_reachable: register_int_gauge_with_registry!(
+            opts(
+                "upstreams_reachable",
+                "1 if every enabled source was fetched in the last refresh, 0 otherwise",
+            ),
+            registry
+        )
+        .expect("Failed to create gauge upstreams_reachable"),
+        circuit_open: register_int_gauge_vec_with_registry!(
+            opts(
+                "circuit_open",
+                "1 while a source is skipped after failing too many refreshes in a row",
+            ),
+            &["source"],
+            registry
+        )
+        .expect("Failed to create gauge vector circuit_open"),
+    }
+}
+
+impl Metrics {
+    /// Drops the metrics of a disabled source ("github", "airtable" or "hcb")
+    /// from `registry` so they never show up, rather than sitting at 0
+    pub fn unregister_source(&self, registry: &Registry, source: &str) {
+        let collectors: Vec<Box<dyn Collector>> = match source {
+            "github" => vec![
+                Box::new(self.pr_reviewer_stats.clone()),
+                Box::new(self.pr_reviewer_stats_merged.clone()),
+                Box::new(self.pr_merger_stats.clone()),
+                Box::new(self.pr_review_stats.clone()),
+                Box::new(self.submitted_projects.clone()),
+                Box::new(self.total_submitted_projects.clone()),
+                Box::new(self.submitted_projects_stale.clone()),
+                Box::new(self.submitted_projects_delta.clone()),
+                Box::new(self.submitted_projects_by_category.clone()),
+                Box::new(self.submitted_projects_by_readme.clone()),
+                Box::new(self.projects_requested_dollars_total.clone()),
+                Box::new(self.projects_missing_manifest.clone()),
+                Box::new(self.git.clones.clone()),
+                Box::new(self.git.clone_failures.clone()),
+                Box::new(self.waiting_review.clone()),
+                Box::new(self.waiting_review_stale.clone()),
+                Box::new(self.onboard_prs.clone()),
+                Box::new(self.onboard_prs_stale.clone()),
+                Box::new(self.github_rate_limit_remaining.clone()),
+            ],
+            "airtable" => vec![
+                Box::new(self.airtable_records.clone()),
+                Box::new(self.airtable_records_stale.clone()),
+                Box::new(self.airtable_approval_seconds.clone()),
+                Box::new(self.airtable_approval_ratio.clone()),
+                Box::new(self.airtable.rate_limited.clone()),
+                Box::new(self.airtable.auth_errors.clone()),
+                Box::new(self.airtable.views_configured.clone()),
+                Box::new(self.airtable.views_fetched.clone()),
+            ],
+            "hcb" => vec![
+                Box::new(self.transfers_count.clone()),
+                Box::new(self.transfers_count_by_month.clone()),
+                Box::new(self.avg_grant.clone()),
+                Box::new(self.avg_grant_smoothed.clone()),
+                Box::new(self.median_grant.clone()),
+                Box::new(self.max_grant.clone()),
+                Box::new(self.grant_range_dollars.clone()),
+                Box::new(self.grants_per_day.clone()),
+                Box::new(self.total_grants_dollars.clone()),
+                Box::new(self.avg_grant_stale.clone()),
+                Box::new(self.median_grant_stale.clone()),
+                Box::new(self.max_grant_stale.clone()),
+                Box::new(self.total_grants_dollars_stale.clone()),
+                Box::new(self.oldest_transfer_timestamp.clone()),
+                Box::new(self.newest_transfer_timestamp.clone()),
+                Box::new(self.transfers_by_category.clone()),
+                Box::new(self.distinct_recipients.clone()),
+                Box::new(self.distinct_programs.clone()),
+                Box::new(self.transfers_unknown_program.clone()),
+                Box::new(self.grant_amount_dollars.clone()),
+                Box::new(self.transfers_total.clone()),
+                Box::new(self.hcb.parse_errors.clone()),
+                Box::new(self.hcb.pages_fetched.clone()),
+                Box::new(self.hcb.raw_transfers.clone()),
+                Box::new(self.hcb.filtered_out.clone()),
+                Box::new(self.hcb.response_bytes.clone()),
+            ],
+            _ => Vec::new(),
+        };
+        for collector in collectors {
+            registry
+                .unregister(collector)
+                .expect("Cannot unregister metric");
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct HealthState {
+    /// Set once a metrics update first succeeded for every enabled source
+    pub ready: AtomicBool,
+    /// Whether the most recent metrics update ran to completion
+    pub last_cycle_completed: AtomicBool,
+}
+
+/// Tracks how many refresh cycles in a row each source has failed, a source
+/// failing several times within one cycle still only counts once
+pub struct FailureStreaks {
+    gauge: IntGaugeVec,
+    streaks: HashMap<&'static str, i64>,
+    failed: HashSet<&'static str>,
+}
+
+impl FailureStreaks {
+    pub fn new(gauge: IntGaugeVec, sources: &[&'static str]) -> Self {
+        for source in sources {
+            gauge.with_label_values(&[source]).set(0);
+        }
+        FailureStreaks {
+            gauge,
+            streaks: sources.iter().map(|source| (*source, 0)).collect(),
+            failed: HashSet::new(),
+        }
+    }
+
+    pub fn record_failure(&mut self, source: &'static str) {
+        self.failed.insert(source);
+    }
+
+    /// Bumps the streak of every source that failed this cycle and resets the
+    /// rest, returning whether every source succeeded
+    pub fn finish_cycle(&mut self) -> bool {
+        let all_succeeded = self.failed.is_empty();
+        for (source, streak) in self.streaks.iter_mut() {
+            if self.failed.contains(source) {
+                *streak += 1;
+            } else {
+                *streak = 0;
+            }
+            self.gauge.with_label_values(&[source]).set(*streak);
+        }
+        self.failed.clear();
+        all_succeeded
+    }
+
+    /// Whether `source` failed so far this cycle
+    pub fn has_failed(&self, source: &str) -> bool {
+        self.failed.contains(source)
+    }
+}
+
+/// Keeps `transfers_total` counting each grant once however often it's
+/// re-fetched. Ids are only unique within an org, so they're remembered per org.
+pub struct SeenTransfers {
+    counter: IntCounterVec,
+    seen: HashSet<(String, String)>,
+}
+
+impl SeenTransfers {
+    pub fn new(counter: IntCounterVec) -> Self {
+        SeenTransfers {
+            counter,
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Counts the transfers of `org` not seen before and returns them
+    pub fn observe<'a>(&mut self, org: &str, transfers: &'a [Transfer]) -> Vec<&'a Transfer> {
+        let new: Vec<&Transfer> = transfers
+            .iter()
+            .filter(|transfer| self.seen.insert((org.to_string(), transfer.id.clone())))
+            .collect();
+        self.counter
+            .with_label_values(&[org])
+            .inc_by(new.len() as u64);
+        new
+    }
+}
+
+/// Stops fetching a source for `cooldown` once it failed `threshold` cycles
+/// in a row. The first cycle after the cooldown is a trial: a success closes
+/// the circuit again, a failure reopens it for another cooldown. A zero
+/// cooldown turns the breaker off.
+pub struct CircuitBreaker {
+    gauge: IntGaugeVec,
+    threshold: u32,
+    cooldown: Duration,
+    circuits: HashMap<&'static str, Circuit>,
+}
+
+#[derive(Default)]
+struct Circuit {
+    failures: u32,
+    opened_at: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(
+        gauge: IntGaugeVec,
+        threshold: u32,
+        cooldown: Duration,
+        sources: &[&'static str],
+    ) -> Self {
+        for source in sources {
+            gauge.with_label_values(&[source]).set(0);
+        }
+        CircuitBreaker {
+            gauge,
+            threshold: threshold.max(1),
+            cooldown,
+            circuits: HashMap::new(),
+        }
+    }
+
+    /// Whether `source` should be fetched this cycle, false while its circuit
+    /// is open and cooling down
+    pub fn allows(&self, source: &str) -> bool {
+        match self
+            .circuits
+            .get(source)
+            .and_then(|circuit| circuit.opened_at)
+        {
+            Some(opened_at) => opened_at.elapsed() >= self.cooldown,
+            None => true,
+        }
+    }
+
+    /// Records how a cycle that fetched `source` went
+    pub fn record(&mut self, source: &'static str, succeeded: bool) {
+        if self.cooldown.is_zero() {
+            return;
+        }
+        let circuit = self.circuits.entry(source).or_default();
+        if succeeded {
+            if circuit.opened_at.is_some() {
+                info!("{} recovered, closing its circuit", source);
+            }
+            *circuit = Circuit::default();
+        } else {
+            circuit.failures += 1;
+            if circuit.opened_at.is_some() || circuit.failures >= self.threshold {
+                warn!(
+                    "{} failed {} refreshes in a row, skipping it for {:?}",
+                    source, circuit.failures, self.cooldown
+                );
+                circuit.opened_at = Some(Instant::now());
+            }
+        }
+        self.gauge
+            .with_label_values(&[source])
+            .set(circuit.opened_at.is_some().into());
+    }
+}
+
+/// A gauge or gauge vector `MetricsState` can set series of. Integer gauges
+/// round the value, which counts survive exactly.
+pub trait GaugeSeries: Collector {
+    fn set_series(&self, labels: &[&str], value: f64);
+}
+
+impl GaugeSeries for GaugeVec {
+    fn set_series(&self, labels: &[&str], value: f64) {
+        self.with_label_values(labels).set(value);
+    }
+}
+
+impl GaugeSeries for IntGaugeVec {
+    fn set_series(&self, labels: &[&str], value: f64) {
+        self.with_label_values(labels).set(value.round() as i64);
+    }
+}
+
+/// A plain gauge has a single series, set through it with no labels
+impl GaugeSeries for IntGauge {
+    fn set_series(&self, _labels: &[&str], value: f64) {
+        self.set(value.round() as i64);
+    }
+}
+
+/// Last successfully computed value of each gauge series set through it, so
+/// every source shares one policy for failed fetches: the series goes back to
+/// its last good value and its companion `{metric}_stale` gauge is set to 1.
+#[derive(Default)]
+pub struct MetricsState {
+    last_good: HashMap<(String, Vec<String>), f64>,
+    /// Values `delta` last saw, by name
+    previous: HashMap<String, f64>,
+}
+
+impl MetricsState {
+    /// Series remembered at most, later ones are still set but not cached
+    const MAX_SERIES: usize = 1024;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets a series to a freshly computed value and remembers it
+    pub fn set_fresh(
+        &mut self,
+        gauge: &impl GaugeSeries,
+        stale: &impl GaugeSeries,
+        labels: &[&str],
+        value: f64,
+    ) {
+        gauge.set_series(labels, value);
+        stale.set_series(labels, 0.0);
+
+        let key = Self::key(gauge, labels);
+        if self.last_good.len() < Self::MAX_SERIES || self.last_good.contains_key(&key) {
+            self.last_good.insert(key, value);
+        }
+    }
+
+    /// Puts a series back to its last good value after a failed fetch and
+    /// flags it stale. A series that never succeeded is only flagged.
+    pub fn set_stale(
+        &mut self,
+        gauge: &impl GaugeSeries,
+        stale: &impl GaugeSeries,
+        labels: &[&str],
+    ) {
+        if let Some(value) = self.last_good.get(&Self::key(gauge, labels)) {
+            gauge.set_series(labels, *value);
+        }
+        stale.set_series(labels, 1.0);
+    }
+
+    /// `set_stale` for each of `label_sets`, for a source that wasn't fetched
+    /// at all this refresh, e.g. while its circuit is open
+    pub fn set_all_stale<'a>(
+        &mut self,
+        gauge: &impl GaugeSeries,
+        stale: &impl GaugeSeries,
+        label_sets: impl IntoIterator<Item = Vec<&'a str>>,
+    ) {
+        for labels in label_sets {
+            self.set_stale(gauge, stale, &labels);
+        }
+    }
+
+    /// How much `value` changed since the last call for `name`, 0 the first
+    /// time since there's nothing to compare against yet
+    pub fn delta(&mut self, name: &str, value: f64) -> f64 {
+        match self.previous.insert(name.to_string(), value) {
+            Some(previous) => value - previous,
+            None => 0.0,
+        }
+    }
+
+    fn key(gauge: &impl GaugeSeries, labels: &[&str]) -> (String, Vec<String>) {
+        let name = gauge
+            .desc()
+            .first()
+            .map(|desc| desc.fq_name.clone())
+            .unwrap_or_default();
+        (name, labels.iter().map(|label| label.to_string()).collect())
+    }
+}
+
+/// Summary statistics over a set of grants, in dollars
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GrantStats {
+    pub avg: f64,
+    pub median: f64,
+    pub max: f64,
+    pub min: f64,
+    /// `max - min`, 0 for a single grant
+    pub range: f64,
+    pub total: f64,
+    pub count: usize,
+}
+
+/// Running count, sum, min and max of grants in cents, enough for the average
+/// and total without keeping the grants themselves
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GrantTotals {
+    pub count: usize,
+    pub total_cents: u64,
+    pub min_cents: Option<u64>,
+    pub max_cents: Option<u64>,
+}
+
+impl GrantTotals {
+    pub fn add(&mut self, amount_cents: u64) {
+        self.count += 1;
+        self.total_cents += amount_cents;
+        self.min_cents = Some(
+            self.min_cents
+                .map_or(amount_cents, |min| min.min(amount_cents)),
+        );
+        self.max_cents = Some(
+            self.max_cents
+                .map_or(amount_cents, |max| max.max(amount_cents)),
+        );
+    }
+
+    /// The same numbers `grant_stats` gives, except the median, which can't be
+    /// had without every grant and is left at 0
+    pub fn stats(&self) -> GrantStats {
+        if self.count == 0 {
+            return GrantStats::default();
+        }
+        let total = self.total_cents as f64 / 100.0;
+        let min = self.min_cents.unwrap_or(0) as f64 / 100.0;
+        let max = self.max_cents.unwrap_or(0) as f64 / 100.0;
+        GrantStats {
+            avg: total / self.count as f64,
+            median: 0.0,
+            max,
+            min,
+            range: max - min,
+            total,
+            count: self.count,
+        }
+    }
 }
 
 #[derive(Deserialize, PartialEq, Clone)]
@@ -74,3 +1273,2040 @@ pub enum State {
     merged,
     any,
 }
+
+/// Default API roots, overridable so the fetchers can be pointed at a mock
+pub const HCB_API_URL: &str = "https://hcb.hackclub.com/api/v3";
+pub const AIRTABLE_API_URL: &str = "https://api.airtable.com/v0";
+pub const GITHUB_API_URL: &str = "https://api.github.com";
+
+/// Memo prefixes (matched case-insensitively) and the category they count towards
+pub const TRANSFER_CATEGORIES: &[(&str, &str)] = &[
+    ("onboard", "onboard"),
+    ("grant", "grant"),
+    ("reimbursement", "reimbursement"),
+    ("refund", "refund"),
+];
+
+/// Categorizes a transfer by its memo prefix, or "uncategorized" if none match.
+pub fn transfer_category(memo: &str) -> &'static str {
+    let memo = memo.trim_start().to_lowercase();
+    TRANSFER_CATEGORIES
+        .iter()
+        .find(|(prefix, _)| memo.starts_with(prefix))
+        .map(|(_, category)| *category)
+        .unwrap_or("uncategorized")
+}
+
+pub fn count_transfers_by_category(transfers: &[Transfer]) -> HashMap<&'static str, u32> {
+    let mut category_counts = HashMap::new();
+    for transfer in transfers {
+        *category_counts
+            .entry(transfer_category(&transfer.transaction.memo))
+            .or_insert(0) += 1;
+    }
+    category_counts
+}
+
+/// Counts the organizations that received at least one of `transfers`, with
+/// every transfer missing a recipient lumped into a single "unknown" one.
+pub fn distinct_recipients(transfers: &[Transfer]) -> u32 {
+    transfers
+        .iter()
+        .map(|transfer| {
+            transfer
+                .recipient
+                .as_ref()
+                .map_or("unknown", |recipient| recipient.id.as_str())
+        })
+        .collect::<HashSet<&str>>()
+        .len() as u32
+}
+
+/// The program a transfer belongs to: the first `[tag]` in its memo, trimmed
+/// and lowercased, e.g. "OnBoard grant [Arcade]" is "arcade". `None` when the
+/// memo has no non-empty tag.
+pub fn transfer_program(memo: &str) -> Option<String> {
+    let (_, rest) = memo.split_once('[')?;
+    let (tag, _) = rest.split_once(']')?;
+    let tag = tag.trim();
+    (!tag.is_empty()).then(|| tag.to_lowercase())
+}
+
+/// Counts the distinct programs among `transfers`, and separately the
+/// transfers without one, which never count as a program of their own.
+pub fn distinct_programs(transfers: &[Transfer]) -> (u32, u32) {
+    let mut programs = HashSet::new();
+    let mut unknown = 0;
+    for transfer in transfers {
+        match transfer_program(&transfer.transaction.memo) {
+            Some(program) => {
+                programs.insert(program);
+            }
+            None => unknown += 1,
+        }
+    }
+    (programs.len() as u32, unknown)
+}
+
+/// Logs a payload that failed to parse at debug level, only when `enabled`
+/// (`DEBUG_DUMP_JSON`) since payloads can contain personal details.
+pub fn dump_json(enabled: bool, source: &str, json: &serde_json::Value) {
+    if enabled {
+        debug!(target: source, "Raw JSON that failed to parse: {}", json);
+    }
+}
+
+/// Longest `Retry-After` we're willing to sit through before retrying
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Sends a request, retrying on 429s, 5xx responses and network errors with
+/// exponential backoff, or for as long as a 429's `Retry-After` asks. Gives up
+/// and returns the last error after `max_retries`. Every 429 bumps
+/// `rate_limited` when given.
+pub async fn send_with_retry(
+    source: &str,
+    request: RequestBuilder,
+    max_retries: u32,
+    rate_limited: Option<&IntCounter>,
+) -> Result<Response, reqwest::Error> {
+    let mut attempt = 0;
+
+    loop {
+        let response = request
+            .try_clone()
+            .expect("Request body cannot be cloned for retries")
+            .send()
+            .await;
+
+        let retryable = match &response {
+            Ok(response) => {
+                response.status() == StatusCode::TOO_MANY_REQUESTS
+                    || response.status().is_server_error()
+            }
+            Err(e) => e.is_connect() || e.is_timeout() || e.is_request(),
+        };
+
+        if !retryable {
+            return response;
+        }
+
+        let retry_after = match &response {
+            Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => {
+                if let Some(rate_limited) = rate_limited {
+                    rate_limited.inc();
+                }
+                response
+                    .headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|retry_after| retry_after.to_str().ok())
+                    .and_then(|retry_after| retry_after.trim().parse::<u64>().ok())
+                    .map(|secs| Duration::from_secs(secs).min(MAX_RETRY_AFTER))
+            }
+            _ => None,
+        };
+        if attempt >= max_retries {
+            return response.and_then(|response| response.error_for_status());
+        }
+
+        attempt += 1;
+        let backoff = Duration::from_millis(500 * 2u64.pow(attempt - 1))
+            .max(retry_after.unwrap_or(Duration::ZERO));
+        match &response {
+            Ok(response) => warn!(
+                target: source,
+                attempt = attempt,
+                max_retries = max_retries,
+                backoff_secs = backoff.as_secs_f64(),
+                status = response.status().as_u16();
+                "Request returned {}, retrying in {:?} (attempt {}/{})",
+                response.status(),
+                backoff,
+                attempt,
+                max_retries
+            ),
+            Err(e) => warn!(
+                target: source,
+                attempt = attempt,
+                max_retries = max_retries,
+                backoff_secs = backoff.as_secs_f64();
+                "Request failed: {}, retrying in {:?} (attempt {}/{})",
+                e,
+                backoff,
+                attempt,
+                max_retries
+            ),
+        }
+        tokio::time::sleep(backoff).await;
+    }
+}
+
+/// Fetches the transfers of every configured organization, keeping each org's
+/// result separate so one failing org doesn't hide the others. Orgs fetched
+/// less than `cache_ttl` ago are served from `cache` instead of hitting HCB again,
+/// the `bool` is whether the transfers were actually fetched this time.
+pub async fn fetch_hcb_orgs(
+    client: &Client,
+    config: &HcbConfig,
+    metrics: &HcbMetrics,
+    cache: &mut HashMap<String, HcbCache>,
+) -> Vec<(String, anyhow::Result<Vec<Transfer>>, bool)> {
+    let mut hcb_data = Vec::new();
+    for org in &config.orgs {
+        if let Some(cached) = cache.get(org) {
+            if cached.last_fetch.elapsed() < config.cache_ttl {
+                info!(target: "hcb", "Using cached transfers for {}", org);
+                hcb_data.push((org.clone(), Ok(cached.transfers.clone()), false));
+                continue;
+            }
+        }
+
+        let transfers = fetch_hcb(client, config, org, metrics).await;
+        if let Ok(transfers) = &transfers {
+            cache.insert(
+                org.clone(),
+                HcbCache {
+                    last_fetch: Instant::now(),
+                    transfers: transfers.clone(),
+                },
+            );
+        }
+        hcb_data.push((org.clone(), transfers, true));
+    }
+    hcb_data
+}
+
+pub async fn fetch_hcb(
+    client: &Client,
+    config: &HcbConfig,
+    org: &str,
+    metrics: &HcbMetrics,
+) -> anyhow::Result<Vec<Transfer>> {
+    let mut transfers: Vec<Transfer> = Vec::new();
+    // Pages can overlap when transfers land mid-pagination, so each id is only
+    // counted the first time it shows up
+    let mut seen_ids: HashSet<String> = HashSet::new();
+    let mut duplicate_transfers = 0;
+    walk_hcb_pages(client, config, org, metrics, |transfer| {
+        if seen_ids.insert(transfer.id.clone()) {
+            transfers.push(transfer);
+        } else {
+            duplicate_transfers += 1;
+        }
+    })
+    .await?;
+
+    if duplicate_transfers > 0 {
+        warn!(
+            target: "hcb",
+            "Dropped {} transfers of {} repeated across pages", duplicate_transfers, org
+        );
+    }
+
+    metrics
+        .raw_transfers
+        .with_label_values(&[org])
+        .set(transfers.len() as i64);
+
+    let before_type_filter = transfers.len();
+    transfers.retain(|transfer| is_grant_type(config, &transfer.transaction.type_));
+    let before_amount_filter = transfers.len();
+    transfers.retain(|transfer| (transfer.amount_cents / 100) <= config.max_grant_dollars);
+
+    metrics
+        .filtered_out
+        .with_label_values(&[org, "type"])
+        .set((before_type_filter - before_amount_filter) as i64);
+    metrics
+        .filtered_out
+        .with_label_values(&[org, "amount"])
+        .set((before_amount_filter - transfers.len()) as i64);
+    Ok(transfers)
+}
+
+/// Like `fetch_hcb`, but folds each page into running totals and drops the
+/// transfers, so memory stays flat however many grants an org has. Transfers
+/// repeated across pages are counted twice, telling them apart needs every id.
+pub async fn fetch_hcb_totals(
+    client: &Client,
+    config: &HcbConfig,
+    org: &str,
+    metrics: &HcbMetrics,
+) -> anyhow::Result<GrantTotals> {
+    let mut totals = GrantTotals::default();
+    let (mut raw_transfers, mut wrong_type, mut too_large) = (0, 0, 0);
+    walk_hcb_pages(client, config, org, metrics, |transfer| {
+        raw_transfers += 1;
+        if !is_grant_type(config, &transfer.transaction.type_) {
+            wrong_type += 1;
+        } else if transfer.amount_cents / 100 > config.max_grant_dollars {
+            too_large += 1;
+        } else {
+            totals.add(transfer.amount_cents);
+        }
+    })
+    .await?;
+
+    metrics
+        .raw_transfers
+        .with_label_values(&[org])
+        .set(raw_transfers);
+    metrics
+        .filtered_out
+        .with_label_values(&[org, "type"])
+        .set(wrong_type);
+    metrics
+        .filtered_out
+        .with_label_values(&[org, "amount"])
+        .set(too_large);
+    Ok(totals)
+}
+
+/// `fetch_hcb_totals` for every configured organization. The transfer cache
+/// is skipped, holding on to the transfers is what this path avoids.
+pub async fn fetch_hcb_org_totals(
+    client: &Client,
+    config: &HcbConfig,
+    metrics: &HcbMetrics,
+) -> Vec<(String, anyhow::Result<GrantTotals>)> {
+    let mut hcb_totals = Vec::new();
+    for org in &config.orgs {
+        let totals = fetch_hcb_totals(client, config, org, metrics).await;
+        hcb_totals.push((org.clone(), totals));
+    }
+    hcb_totals
+}
+
+/// Pages through an organization's transfers, handing every one that
+/// deserializes to `visit` as soon as its page arrives
+async fn walk_hcb_pages(
+    client: &Client,
+    config: &HcbConfig,
+    org: &str,
+    metrics: &HcbMetrics,
+    mut visit: impl FnMut(Transfer),
+) -> anyhow::Result<()> {
+    // HCB pages are 1-indexed
+    let mut page = 1;
+    let mut skipped_transfers = 0;
+
+    loop {
+        let mut request_url: Url = Url::parse(&format!(
+            "{}/organizations/{}/transfers/",
+            config.api_url.trim_end_matches('/'),
+            org
+        ))
+        .expect("Invalid HCB API URL");
+        request_url
+            .query_pairs_mut()
+            .append_pair("per_page", &config.page_size.to_string());
+        request_url
+            .query_pairs_mut()
+            .append_pair("expand", "transaction");
+        request_url
+            .query_pairs_mut()
+            .append_pair("page", &page.to_string());
+
+        let response = send_with_retry(
+            "hcb",
+            client.get(request_url.as_str()),
+            config.max_retries,
+            None,
+        )
+        .await?;
+        let body = response.bytes().await?;
+        metrics
+            .response_bytes
+            .with_label_values(&[org])
+            .observe(body.len() as f64);
+        let json = serde_json::from_slice::<serde_json::Value>(&body)?;
+        info!(
+            target: "hcb",
+            r##"Fetching transfers from page {} from {}'s Hack Club Bank API using, "{}""##,
+            page,
+            org,
+            request_url
+        );
+
+        if json.as_array().is_some_and(|arr| arr.is_empty()) {
+            break;
+        }
+
+        if let Some(raw_transfers) = json.as_array() {
+            for raw_transfer in raw_transfers {
+                match serde_json::from_value::<Transfer>(raw_transfer.clone()) {
+                    Ok(transfer) => visit(transfer),
+                    Err(e) => {
+                        warn!(
+                            target: "hcb",
+                            "Skipping HCB transfer that failed to deserialize: {}", e
+                        );
+                        dump_json(config.dump_json, "hcb", raw_transfer);
+                        skipped_transfers += 1;
+                    }
+                }
+            }
+
+            // A short page is the last one, no need to ask for an empty page after it
+            if raw_transfers.len() < config.page_size as usize {
+                break;
+            }
+        } else {
+            // An error object rather than a page, asking for the next page
+            // would just get the same error back
+            error!(target: "hcb", "Failed to parse JSON array from response");
+            dump_json(config.dump_json, "hcb", &json);
+            anyhow::bail!(
+                "HCB returned a non-array response for {} page {}",
+                org,
+                page
+            );
+        }
+        page += 1;
+    }
+
+    metrics.pages_fetched.with_label_values(&[org]).set(page);
+    metrics
+        .parse_errors
+        .with_label_values(&[org])
+        .set(skipped_transfers);
+    Ok(())
+}
+
+/// Whether a transaction type passes the include and exclude lists, compared
+/// case-insensitively
+pub fn is_grant_type(config: &HcbConfig, type_: &str) -> bool {
+    (config.include_types.is_empty()
+        || config
+            .include_types
+            .iter()
+            .any(|include| include.eq_ignore_ascii_case(type_)))
+        && !config
+            .exclude_types
+            .iter()
+            .any(|exclude| exclude.eq_ignore_ascii_case(type_))
+}
+
+pub fn count_transfers(transfers: &anyhow::Result<Vec<Transfer>>) -> i64 {
+    match transfers {
+        Ok(count) => return count.len() as i64,
+        Err(e) => {
+            error!(target: "hcb", "Failed to fetch transfers: {}", e);
+            return 0;
+        }
+    };
+}
+
+pub fn avg_grant(transfers: &anyhow::Result<Vec<Transfer>>) -> f64 {
+    match transfers {
+        Ok(transfers) if transfers.is_empty() => return 0.0,
+        Ok(transfers) => {
+            // Sum in cents and convert once, so $99.99 isn't counted as $99
+            let mut total_cents = 0;
+            for transfer in transfers {
+                total_cents += transfer.amount_cents;
+            }
+            return total_cents as f64 / 100.0 / transfers.len() as f64;
+        }
+        Err(e) => {
+            error!(target: "hcb", "Failed to fetch transfers: {}", e);
+            return 0.0;
+        }
+    };
+}
+
+/// Counts the transfers dated within `window` of `now`. Transfers without a
+/// usable date are left out.
+pub fn count_transfers_since(
+    transfers: &[Transfer],
+    now: DateTime<Utc>,
+    window: chrono::Duration,
+) -> u32 {
+    transfers
+        .iter()
+        .filter(|transfer| match transfer.date {
+            Some(date) => date <= now && now - date <= window,
+            None => false,
+        })
+        .count() as u32
+}
+
+/// Calendar months (`YYYY-MM`, UTC) of the `months` months up to and including
+/// the one `now` falls in, oldest first
+pub fn trailing_months(now: DateTime<Utc>, months: u32) -> Vec<String> {
+    let this_month = now
+        .date_naive()
+        .with_day(1)
+        .expect("Every month has a first day");
+    (0..months)
+        .rev()
+        .filter_map(|back| this_month.checked_sub_months(Months::new(back)))
+        .map(|month| month.format("%Y-%m").to_string())
+        .collect()
+}
+
+/// Number of transfers in each of the `trailing_months`, 0 for months without
+/// any. Undated transfers and ones outside the window are left out.
+pub fn count_transfers_by_month(
+    transfers: &[Transfer],
+    now: DateTime<Utc>,
+    months: u32,
+) -> Vec<(String, u32)> {
+    let mut counts: Vec<(String, u32)> = trailing_months(now, months)
+        .into_iter()
+        .map(|month| (month, 0))
+        .collect();
+    for date in transfers.iter().filter_map(|transfer| transfer.date) {
+        let month = date.format("%Y-%m").to_string();
+        if let Some((_, count)) = counts.iter_mut().find(|(bucket, _)| *bucket == month) {
+            *count += 1;
+        }
+    }
+    counts
+}
+
+/// Average grants per day over the trailing `window_days` before `now`, from
+/// the dated transfers only
+pub fn grants_per_day(transfers: &[Transfer], now: DateTime<Utc>, window_days: u32) -> f64 {
+    if window_days == 0 {
+        return 0.0;
+    }
+    let window = chrono::Duration::days(window_days.into());
+    count_transfers_since(transfers, now, window) as f64 / window_days as f64
+}
+
+/// Oldest and newest transfer dates, ignoring transfers without a date.
+pub fn transfer_date_range(transfers: &[Transfer]) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut dates = transfers.iter().filter_map(|transfer| transfer.date);
+    let first = dates.next()?;
+    Some(dates.fold((first, first), |(oldest, newest), date| {
+        (oldest.min(date), newest.max(date))
+    }))
+}
+
+/// Folds `value` into an exponential moving average, starting from `value`
+/// itself instead of ramping up from 0
+pub fn ema(previous: Option<f64>, value: f64, alpha: f64) -> f64 {
+    match previous {
+        Some(previous) => alpha * value + (1.0 - alpha) * previous,
+        None => value,
+    }
+}
+
+/// Seconds between submission and approval of each record, skipping records
+/// missing either timestamp
+pub fn approval_durations(
+    records: &[AirtableRecord],
+    submitted_field: Option<&str>,
+    approved_field: &str,
+) -> Vec<f64> {
+    records
+        .iter()
+        .filter_map(|record| {
+            let submitted = match submitted_field {
+                Some(field) => record.fields.get(field)?.as_str()?,
+                None => record.created_time.as_str(),
+            };
+            let submitted = parse_date(submitted)?;
+            let approved = parse_date(record.fields.get(approved_field)?.as_str()?)?;
+            Some((approved - submitted).num_seconds() as f64)
+        })
+        .collect()
+}
+
+/// Mean, median and 90th percentile of `values`, `None` when there are none
+pub fn mean_p50_p90(values: &mut [f64]) -> Option<(f64, f64, f64)> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    // Nearest-rank percentiles
+    let percentile = |p: f64| values[((p * values.len() as f64).ceil() as usize).max(1) - 1];
+    Some((mean, percentile(0.5), percentile(0.9)))
+}
+
+/// Share of reviewed-or-waiting records that were approved, 0 when there are none
+pub fn approval_ratio(approved: i64, pending: i64) -> f64 {
+    let total = approved + pending;
+    if total <= 0 {
+        return 0.0;
+    }
+    approved as f64 / total as f64
+}
+
+/// Computes grant statistics, leaving everything at 0 for an empty list
+/// rather than dividing by zero.
+pub fn grant_stats(transfers: &[Transfer]) -> GrantStats {
+    if transfers.is_empty() {
+        return GrantStats::default();
+    }
+
+    let mut amounts: Vec<f64> = transfers
+        .iter()
+        .map(|transfer| transfer.amount_cents as f64 / 100.0)
+        .collect();
+    amounts.sort_by(|a, b| a.total_cmp(b));
+
+    // Sum the cents before converting so individual grants aren't truncated
+    let total_cents: u64 = transfers.iter().map(|transfer| transfer.amount_cents).sum();
+    let total = total_cents as f64 / 100.0;
+
+    let count = amounts.len();
+    let median = if count % 2 == 0 {
+        (amounts[count / 2 - 1] + amounts[count / 2]) / 2.0
+    } else {
+        amounts[count / 2]
+    };
+
+    GrantStats {
+        avg: total / count as f64,
+        median,
+        max: amounts[count - 1],
+        min: amounts[0],
+        range: amounts[count - 1] - amounts[0],
+        total,
+        count,
+    }
+}
+
+/// Which Airtable table to count records in, and how
+pub struct AirtableConfig {
+    pub api_url: String,
+    /// Nothing is counted without a key
+    pub api_key: Option<String>,
+    pub base_id: String,
+    pub table: String,
+    pub views: Vec<String>,
+    /// Counted on top of the views when set, see `AirtableQuery::filter_formula`
+    pub filter_formula: Option<String>,
+    /// Field holding when a submission was made, the record's creation time when unset
+    pub submitted_field: Option<String>,
+    /// Field holding when a submission was approved, approval times are only
+    /// computed when it's set
+    pub approved_field: Option<String>,
+    pub max_retries: u32,
+    /// Log pages that fail to parse, see `dump_json`
+    pub dump_json: bool,
+}
+
+/// Posts `text` to a Slack incoming webhook. Not retried, a repeated post would
+/// show up twice in the channel.
+pub async fn post_slack_message(
+    client: &Client,
+    webhook_url: &str,
+    text: &str,
+) -> Result<(), reqwest::Error> {
+    client
+        .post(webhook_url)
+        .json(&serde_json::json!({ "text": text }))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// PUTs `metric_families` to a Pushgateway under `job`, replacing everything
+/// that job pushed before so series that went away don't linger there.
+pub async fn push_metrics(
+    client: &Client,
+    pushgateway_url: &str,
+    job: &str,
+    metric_families: &[MetricFamily],
+) -> anyhow::Result<()> {
+    let mut url = Url::parse(pushgateway_url)?;
+    url.path_segments_mut()
+        .map_err(|()| anyhow::anyhow!("{} cannot be a Pushgateway URL", pushgateway_url))?
+        .pop_if_empty()
+        .extend(["metrics", "job", job]);
+
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    encoder.encode(metric_families, &mut body)?;
+
+    client
+        .put(url)
+        .header(CONTENT_TYPE, encoder.format_type())
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Counts the records in each configured view, keeping each view's result
+/// separate so one failing view doesn't hide the others. How many views there
+/// are and how many were counted go to `views_configured` and `views_fetched`.
+pub async fn airtable_records_by_view(
+    client: &Client,
+    config: &AirtableConfig,
+    metrics: &AirtableMetrics,
+) -> Vec<(String, anyhow::Result<i64>)> {
+    let mut records = Vec::new();
+    for view in &config.views {
+        let count = airtable_verifications(
+            client,
+            config,
+            metrics,
+            &AirtableQuery {
+                view: Some(view),
+                filter_formula: None,
+                fields: &[],
+            },
+        )
+        .await;
+        records.push((view.clone(), count));
+    }
+    metrics.views_configured.set(config.views.len() as i64);
+    metrics
+        .views_fetched
+        .set(records.iter().filter(|(_, count)| count.is_ok()).count() as i64);
+    records
+}
+
+/// Which records of a table `airtable_verifications` counts, every record when both are unset
+pub struct AirtableQuery<'a> {
+    pub view: Option<&'a str>,
+    /// Passed through as Airtable's `filterByFormula`, e.g. `{Status}='Flagged'`
+    pub filter_formula: Option<&'a str>,
+    /// Only these fields are returned, all of them when empty
+    pub fields: &'a [&'a str],
+}
+
+pub async fn airtable_verifications(
+    client: &Client,
+    config: &AirtableConfig,
+    metrics: &AirtableMetrics,
+    query: &AirtableQuery<'_>,
+) -> anyhow::Result<i64> {
+    airtable_records(client, config, metrics, query)
+        .await
+        .map(|records| records.len() as i64)
+}
+
+/// Fetches every record matching `query`, following Airtable's pagination.
+/// Without an API key there's nothing to fetch and no records are returned.
+pub async fn airtable_records(
+    client: &Client,
+    config: &AirtableConfig,
+    metrics: &AirtableMetrics,
+    query: &AirtableQuery<'_>,
+) -> anyhow::Result<Vec<AirtableRecord>> {
+    // Airtable caps a single page at 100 records, the rest come back via `offset`
+    let page_size = 100;
+    let mut page_offset: Option<String> = None;
+    let mut all_records = Vec::new();
+    let true_api_key;
+    let mut page_offset_count = 0;
+
+    match &config.api_key {
+        Some(key) => {
+            info!(target: "airtable", "Airtable API key found");
+            true_api_key = key;
+        }
+        None => {
+            info!(target: "airtable", "Airtable API key not found");
+            return Ok(all_records);
+        }
+    }
+    loop {
+        let mut request_url: Url = Url::parse(&config.api_url).expect("Invalid Airtable API URL");
+        request_url
+            .path_segments_mut()
+            .unwrap()
+            .pop_if_empty()
+            .push(&config.base_id)
+            .push(&config.table);
+        request_url
+            .query_pairs_mut()
+            .append_pair("pageSize", &page_size.to_string());
+        if let Some(view) = query.view {
+            request_url.query_pairs_mut().append_pair("view", view);
+        }
+        if let Some(formula) = query.filter_formula {
+            request_url
+                .query_pairs_mut()
+                .append_pair("filterByFormula", formula);
+        }
+        for field in query.fields {
+            request_url.query_pairs_mut().append_pair("fields[]", field);
+        }
+
+        match &page_offset {
+            Some(offset) => {
+                request_url
+                    .query_pairs_mut()
+                    .append_pair("offset", offset.as_str());
+            }
+            None => {}
+        }
+
+        let auth_token: String = format!("Bearer {}", true_api_key);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&auth_token).expect("Invalid header value"),
+        );
+
+        let response = send_with_retry(
+            "airtable",
+            client.get(request_url.as_str()).headers(headers),
+            config.max_retries,
+            Some(&metrics.rate_limited),
+        )
+        .await?;
+
+        // Otherwise a bad key would read as a view with no records
+        let status = response.status();
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            metrics.auth_errors.inc();
+            error!(target: "airtable", "Airtable refused the API key with {}", status);
+            anyhow::bail!("Airtable refused the API key with {}", status);
+        }
+        let json = response.json::<serde_json::Value>().await?;
+        info!(
+            target: "airtable",
+            r##"Fetching transfers from OnBoard's AirTable accepted verision forms using, "{}", on page {}."##,
+            request_url,
+            page_offset_count + 1
+        );
+
+        let raw_data = json;
+
+        // An unknown view (or field, or formula) comes back as an error object
+        // rather than records; counting it as 0 records would hide the typo
+        if let Some(error) = raw_data.get("error") {
+            error!(
+                target: "airtable",
+                "Airtable rejected the query for view {:?}: {}", query.view, error
+            );
+            let kind = error
+                .get("type")
+                .and_then(|kind| kind.as_str())
+                .or_else(|| error.as_str())
+                .unwrap_or("unknown error");
+            anyhow::bail!("Airtable returned {} for view {:?}", kind, query.view);
+        }
+        if !status.is_success() {
+            anyhow::bail!("Airtable answered {} for view {:?}", status, query.view);
+        }
+
+        // Counting the pages so far would undercount without anyone noticing
+        let page = match AirtableResponse::deserialize(&raw_data) {
+            Ok(page) => page,
+            Err(e) => {
+                error!(target: "airtable", "The AirTable JSON is Invalid: {}", e);
+                dump_json(config.dump_json, "airtable", &raw_data);
+                anyhow::bail!(
+                    "Airtable page {} for view {:?} doesn't parse: {}",
+                    page_offset_count + 1,
+                    query.view,
+                    e
+                );
+            }
+        };
+        all_records.extend(page.records);
+
+        match page.offset {
+            Some(offset) => {
+                page_offset = Some(offset);
+                page_offset_count += 1;
+            }
+            None => {
+                if page_offset_count > 0 {
+                    info!(
+                        target: "airtable",
+                        "[{}]Multiple pages of AirTable data fetched",
+                        page_offset_count + 1
+                    );
+                }
+                return Ok(all_records);
+            }
+        }
+    }
+}
+
+/// Lists what a projects tree contains, so the counting doesn't depend on
+/// where the tree lives. Paths are relative to the root of the tree, which is
+/// the empty path.
+pub trait ProjectSource {
+    /// Names of the directories directly inside `dir`, hidden ones included
+    fn dirs(&self, dir: &Path) -> anyhow::Result<Vec<String>>;
+    /// Names of everything directly inside `dir`, files and directories
+    fn entries(&self, dir: &Path) -> anyhow::Result<Vec<String>>;
+    /// Contents of the file at `path`, `None` when there's no such file
+    fn read_file(&self, path: &Path) -> Option<String>;
+}
+
+/// A checkout of the projects tree on disk
+impl ProjectSource for Path {
+    fn dirs(&self, dir: &Path) -> anyhow::Result<Vec<String>> {
+        let dirs = fs::read_dir(self.join(dir))?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect();
+        Ok(dirs)
+    }
+
+    fn entries(&self, dir: &Path) -> anyhow::Result<Vec<String>> {
+        let entries = fs::read_dir(self.join(dir))?
+            .filter_map(Result::ok)
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect();
+        Ok(entries)
+    }
+
+    fn read_file(&self, path: &Path) -> Option<String> {
+        fs::read_to_string(self.join(path)).ok()
+    }
+}
+
+/// Directories count as projects unless they're hidden (e.g. `.github`)
+fn visible_dirs<S: ProjectSource + ?Sized>(source: &S, dir: &Path) -> anyhow::Result<Vec<String>> {
+    let mut dirs = source.dirs(dir)?;
+    dirs.retain(|name| !name.starts_with('.'));
+    Ok(dirs)
+}
+
+/// Counts the projects in `source`, skipping hidden directories like `.github`
+pub fn count_projects<S: ProjectSource + ?Sized>(source: &S) -> anyhow::Result<f64> {
+    Ok(visible_dirs(source, Path::new(""))?.len() as f64)
+}
+
+/// Counts the project directories inside each top-level category directory,
+/// for repos that group projects as `projects/<category>/<project>`.
+pub fn count_project_categories<S: ProjectSource + ?Sized>(source: &S) -> HashMap<String, f64> {
+    let mut category_counts = HashMap::new();
+
+    let categories = match visible_dirs(source, Path::new("")) {
+        Ok(categories) => categories,
+        Err(e) => {
+            warn!("Failed to read the projects directory: {}", e);
+            return category_counts;
+        }
+    };
+
+    for category in categories {
+        let count = match visible_dirs(source, Path::new(&category)) {
+            Ok(projects) => projects.len(),
+            Err(e) => {
+                warn!("Failed to read category {}: {}", category, e);
+                continue;
+            }
+        };
+        category_counts.insert(category, count as f64);
+    }
+    category_counts
+}
+
+/// Counts the project directories by whether they contain a README, with the
+/// ones that can't be read counted as "unknown".
+pub fn count_projects_by_readme<S: ProjectSource + ?Sized>(
+    source: &S,
+) -> HashMap<&'static str, f64> {
+    let mut readme_counts = HashMap::from([("true", 0.0), ("false", 0.0), ("unknown", 0.0)]);
+
+    let projects = match visible_dirs(source, Path::new("")) {
+        Ok(projects) => projects,
+        Err(e) => {
+            warn!("Failed to read the projects directory: {}", e);
+            return HashMap::new();
+        }
+    };
+
+    for project in projects {
+        let has_readme = match source.entries(Path::new(&project)) {
+            Ok(files) => {
+                let has_readme = files
+                    .iter()
+                    .any(|file| file.to_lowercase().starts_with("readme"));
+                if has_readme {
+                    "true"
+                } else {
+                    "false"
+                }
+            }
+            Err(e) => {
+                warn!("Failed to read project {}: {}", project, e);
+                "unknown"
+            }
+        };
+        *readme_counts.entry(has_readme).or_insert(0.0) += 1.0;
+    }
+    readme_counts
+}
+
+/// Project manifest file names, tried in order
+pub const MANIFEST_FILES: [&str; 2] = ["project.yml", "project.yaml"];
+
+/// Sums the dollars requested in each project's manifest, returning the total
+/// and how many projects have no manifest that parses.
+pub fn sum_requested_dollars<S: ProjectSource + ?Sized>(source: &S) -> Option<(f64, i64)> {
+    let projects = match visible_dirs(source, Path::new("")) {
+        Ok(projects) => projects,
+        Err(e) => {
+            warn!("Failed to read the projects directory: {}", e);
+            return None;
+        }
+    };
+
+    let mut requested_dollars = 0.0;
+    let mut missing_manifest = 0;
+    for project in projects {
+        let manifest = MANIFEST_FILES.iter().find_map(|name| {
+            let path = Path::new(&project).join(name);
+            let raw = source.read_file(&path)?;
+            match serde_yaml::from_str::<ProjectManifest>(&raw) {
+                Ok(manifest) => Some(manifest),
+                Err(e) => {
+                    warn!("Failed to parse {}: {}", path.display(), e);
+                    None
+                }
+            }
+        });
+        match manifest {
+            Some(manifest) => requested_dollars += manifest.requested_dollars,
+            None => missing_manifest += 1,
+        }
+    }
+    Some((requested_dollars, missing_manifest))
+}
+
+/// Every OnBoard pull request, across all pages. Any failed page fails the
+/// whole list, so the review counts are never computed from part of it.
+pub async fn fetch_pull_requests(
+    client: &Client,
+    api_url: &str,
+    github_api_key: Option<String>,
+    max_retries: u32,
+    rate_limit_remaining: &IntGaugeVec,
+) -> anyhow::Result<Vec<PullRequest>> {
+    let mut page_num = 1;
+    let mut headers = HeaderMap::new();
+
+    if let Some(api_key) = &github_api_key {
+        let auth_token = format!("Bearer {}", api_key);
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&auth_token).expect("Invalid header value"),
+        );
+        info!(target: "github", "GitHub API key found");
+    } else {
+        info!(target: "github", "No GitHub API key found");
+    }
+
+    let mut pull_requests: Vec<PullRequest> = vec![];
+    // let mut number_of_times = 0;
+    loop {
+        let mut url: Url = Url::parse(&format!(
+            "{}/repos/hackclub/onboard/pulls",
+            api_url.trim_end_matches('/')
+        ))
+        .expect("Invalid GitHub API URL");
+        url.query_pairs_mut().append_pair("state", "all");
+        url.query_pairs_mut().append_pair("per_page", "100");
+        url.query_pairs_mut()
+            .append_pair("page", &page_num.to_string());
+
+        info!(target: "github", "Fetching pull requests from {}", url);
+
+        let response = send_with_retry(
+            "github",
+            client.get(url.as_str()).headers(headers.clone()),
+            max_retries,
+            None,
+        )
+        .await?;
+
+        if github_api_key.is_some() {
+            record_rate_limit(&response, rate_limit_remaining, "rest");
+        }
+
+        let page: Vec<PullRequest> =
+            response.error_for_status()?.json().await.with_context(|| {
+                format!("GitHub returned an unexpected pulls page {}", page_num)
+            })?;
+
+        if page.is_empty() {
+            return Ok(pull_requests);
+        }
+
+        pull_requests.extend(page.into_iter().map(PullRequest::is_merged));
+
+        info!(
+            target: "github",
+            "Number of fetched pull requests {}.",
+            pull_requests.len()
+        );
+        page_num += 1;
+    }
+}
+
+/// Merges and reviews per user over every merged OnBoard PR. `None` without a
+/// token, or when any page fails, since a partial walk would undercount.
+pub async fn github_pr_stats(
+    client: &Client,
+    api_url: &str,
+    github_token: Option<String>,
+    rate_limit_remaining: &IntGaugeVec,
+) -> Option<HashMap<String, (u32, u32)>> {
+    let mut stats: HashMap<String, (u32, u32)> = HashMap::new();
+
+    let token = match github_token {
+        Some(token) => token,
+        None => {
+            warn!("No GITHUB_TOKEN found, skipping GitHub GraphQL PR stats");
+            return None;
+        }
+    };
+
+    let query = r#"
+        query($cursor: String) {
+            repository(owner: "hackclub", name: "OnBoard") {
+                pullRequests(states: MERGED, first: 100, after: $cursor) {
+                    pageInfo { hasNextPage endCursor }
+                    nodes {
+                        mergedBy { login }
+                        reviews(first: 100) { nodes { author { login } } }
+                    }
+                }
+            }
+        }
+    "#;
+
+    let mut cursor: Option<String> = None;
+
+    loop {
+        let body = serde_json::json!({
+            "query": query,
+            "variables": { "cursor": cursor },
+        });
+
+        let response = client
+            .post(format!("{}/graphql", api_url.trim_end_matches('/')))
+            .header(AUTHORIZATION, format!("Bearer {}", token))
+            .json(&body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+
+        let json = match response {
+            Ok(response) => {
+                record_rate_limit(&response, rate_limit_remaining, "graphql");
+                match response.json::<serde_json::Value>().await {
+                    Ok(json) => json,
+                    Err(e) => {
+                        warn!("Failed to parse GitHub GraphQL response: {}", e);
+                        return None;
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("Failed to fetch GitHub GraphQL PR stats: {}", e);
+                return None;
+            }
+        };
+
+        // GraphQL reports query errors with a 200, possibly next to partial data
+        if let Some(errors) = json.get("errors") {
+            warn!("GitHub GraphQL returned errors: {}", errors);
+            return None;
+        }
+
+        let pull_requests = &json["data"]["repository"]["pullRequests"];
+        let nodes = match pull_requests["nodes"].as_array() {
+            Some(nodes) => nodes,
+            None => {
+                warn!("GitHub GraphQL response has no pull requests: {}", json);
+                return None;
+            }
+        };
+
+        for pr in nodes {
+            if let Some(login) = pr["mergedBy"]["login"].as_str() {
+                stats.entry(login.to_string()).or_insert((0, 0)).0 += 1;
+            }
+
+            for review in pr["reviews"]["nodes"].as_array().into_iter().flatten() {
+                if let Some(login) = review["author"]["login"].as_str() {
+                    stats.entry(login.to_string()).or_insert((0, 0)).1 += 1;
+                }
+            }
+        }
+
+        if pull_requests["pageInfo"]["hasNextPage"].as_bool() != Some(true) {
+            return Some(stats);
+        }
+        cursor = pull_requests["pageInfo"]["endCursor"]
+            .as_str()
+            .map(|cursor| cursor.to_string());
+    }
+}
+
+/// Counts the open and merged OnBoard pull requests, only the ones labelled
+/// `label` when set
+pub fn count_onboard_prs(prs: &[PullRequest], label: Option<&str>) -> (i64, i64) {
+    let (mut open, mut merged) = (0, 0);
+    for pr in prs {
+        if label.is_some_and(|label| pr.labels.iter().all(|l| l.name != label)) {
+            continue;
+        }
+        match pr.state {
+            State::open => open += 1,
+            // `fetch_pull_requests` already turned merged PRs' state into `merged`
+            State::merged => merged += 1,
+            _ => {}
+        }
+    }
+    (open, merged)
+}
+
+/// Records GitHub's `x-ratelimit-remaining` header, if present, under `api`
+pub fn record_rate_limit(
+    response: &reqwest::Response,
+    rate_limit_remaining: &IntGaugeVec,
+    api: &str,
+) {
+    let remaining = response
+        .headers()
+        .get("x-ratelimit-remaining")
+        .and_then(|remaining| remaining.to_str().ok())
+        .and_then(|remaining| remaining.parse::<i64>().ok());
+
+    if let Some(remaining) = remaining {
+        rate_limit_remaining
+            .with_label_values(&[api])
+            .set(remaining);
+    }
+}
+
+pub fn parse_reviewer_stats(prs: Vec<PullRequest>, state: State) -> HashMap<String, u32> {
+    let mut reviewer_counts = HashMap::new();
+    for pr in prs {
+        if !pr.labels.is_empty() {
+            // if pr.labels[0].name == "Submission" || pr.labels[0].name == "Dev" {
+            if pr.labels[0].name == "Submission" {
+                match state {
+                    State::open => {
+                        if pr.state == State::open {
+                            for reviewer in pr.assignees {
+                                *reviewer_counts.entry(reviewer.login).or_insert(0) += 1;
+                            }
+                        }
+                    }
+                    State::closed => {
+                        if pr.state == State::closed {
+                            for reviewer in pr.assignees {
+                                *reviewer_counts.entry(reviewer.login).or_insert(0) += 1;
+                            }
+                        }
+                    }
+                    State::merged => {
+                        if pr.state == State::merged {
+                            for reviewer in pr.assignees {
+                                *reviewer_counts.entry(reviewer.login).or_insert(0) += 1;
+                            }
+                        }
+                    }
+                    State::any => {
+                        for reviewer in pr.assignees {
+                            *reviewer_counts.entry(reviewer.login).or_insert(0) += 1;
+                        }
+                    }
+                }
+            } else {
+                info!(
+                    target: "github",
+                    "Pull Request {} is not a submission or dev PR",
+                    pr.number
+                );
+            }
+        }
+    }
+    reviewer_counts
+}
+
+pub fn awaiting_reviews(prs: Vec<PullRequest>) -> u32 {
+    let mut awaiting_reviews = 0;
+    for pr in prs {
+        if pr.labels.is_empty() {
+            continue;
+        }
+
+        if pr.labels[0].name == "Submission" || pr.labels[0].name == "Dev" {
+            if pr.assignees.is_empty() && pr.requested_reviewers.is_empty() && pr.is_open() {
+                awaiting_reviews += 1;
+            }
+        }
+    }
+    awaiting_reviews
+}
+
+impl IsOpen for PullRequest {
+    fn is_open(&self) -> bool {
+        self.state == State::open
+    }
+}
+
+trait IsOpen {
+    fn is_open(&self) -> bool;
+}
+
+trait IsMerged {
+    fn is_merged(self) -> Self;
+}
+
+impl IsMerged for PullRequest {
+    fn is_merged(mut self) -> Self {
+        if self.merged_at.is_some() {
+            self.state = State::merged;
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    fn at(raw: &str) -> DateTime<Utc> {
+        parse_date(raw).expect("Test dates are valid")
+    }
+
+    /// A transfer as HCB lists it, with only the fields the tests care about
+    /// varying
+    fn transfer(amount_cents: u64, memo: &str) -> Transfer {
+        serde_json::from_value(json!({
+            "id": format!("xfr_{}", amount_cents),
+            "object": "transfer",
+            "href": "https://hcb.hackclub.com/api/v3/transfers/xfr",
+            "transaction": {
+                "id": "txn",
+                "object": "transaction",
+                "href": "https://hcb.hackclub.com/api/v3/transactions/txn",
+                "amount_cents": amount_cents,
+                "memo": memo,
+                "type": "transfer",
+            },
+            "amount_cents": amount_cents,
+        }))
+        .expect("Test transfer deserializes")
+    }
+
+    fn dated(date: &str) -> Transfer {
+        let mut transfer = transfer(1000, "OnBoard grant");
+        transfer.date = Some(at(date));
+        transfer
+    }
+
+    fn test_gauge(name: &str) -> IntGaugeVec {
+        IntGaugeVec::new(Opts::new(name, "test gauge"), &["source"]).unwrap()
+    }
+
+    #[test]
+    fn avg_grant_is_zero_without_transfers() {
+        assert_eq!(avg_grant(&Ok(vec![])), 0.0);
+        assert_eq!(avg_grant(&Err(anyhow::anyhow!("down"))), 0.0);
+    }
+
+    #[test]
+    fn avg_grant_keeps_the_cents() {
+        let transfers = vec![transfer(1000, "a"), transfer(1999, "b")];
+        assert_eq!(avg_grant(&Ok(transfers)), 14.995);
+    }
+
+    #[test]
+    fn grant_stats_of_an_odd_count() {
+        let transfers = vec![
+            transfer(1000, "a"),
+            transfer(3000, "b"),
+            transfer(2000, "c"),
+        ];
+        assert_eq!(
+            grant_stats(&transfers),
+            GrantStats {
+                avg: 20.0,
+                median: 20.0,
+                max: 30.0,
+                min: 10.0,
+                range: 20.0,
+                total: 60.0,
+                count: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn grant_stats_median_of_an_even_count() {
+        let transfers = vec![transfer(2000, "a"), transfer(1000, "b")];
+        assert_eq!(grant_stats(&transfers).median, 15.0);
+        assert_eq!(grant_stats(&[]), GrantStats::default());
+    }
+
+    #[test]
+    fn grant_totals_match_grant_stats_except_the_median() {
+        let transfers = vec![
+            transfer(1000, "a"),
+            transfer(3000, "b"),
+            transfer(2000, "c"),
+        ];
+        let mut totals = GrantTotals::default();
+        for transfer in &transfers {
+            totals.add(transfer.amount_cents);
+        }
+        assert_eq!(
+            totals.stats(),
+            GrantStats {
+                median: 0.0,
+                ..grant_stats(&transfers)
+            }
+        );
+        assert_eq!(GrantTotals::default().stats(), GrantStats::default());
+    }
+
+    #[test]
+    fn parse_date_takes_timestamps_and_bare_dates() {
+        assert_eq!(
+            parse_date("2024-03-05T10:00:00Z"),
+            Utc.with_ymd_and_hms(2024, 3, 5, 10, 0, 0).single()
+        );
+        assert_eq!(
+            parse_date("2024-03-05T10:00:00+01:00"),
+            Utc.with_ymd_and_hms(2024, 3, 5, 9, 0, 0).single()
+        );
+        assert_eq!(
+            parse_date("2024-03-05"),
+            Utc.with_ymd_and_hms(2024, 3, 5, 0, 0, 0).single()
+        );
+        assert_eq!(parse_date("yesterday"), None);
+        assert_eq!(parse_date(""), None);
+    }
+
+    #[test]
+    fn transfer_category_goes_by_memo_prefix() {
+        assert_eq!(transfer_category("OnBoard grant for PCB"), "onboard");
+        assert_eq!(transfer_category("  Grant to Jane"), "grant");
+        assert_eq!(transfer_category("REFUND of order"), "refund");
+        assert_eq!(
+            transfer_category("Pizza for the hackathon"),
+            "uncategorized"
+        );
+    }
+
+    /// Decodes a transfer as `/organizations/{org}/transfers` lists it,
+    /// with `memo` as the memo of its transaction
+    fn hcb_transfer(id: &str, memo: &str) -> Transfer {
+        serde_json::from_value(json!({
+            "id": id,
+            "object": "transfer",
+            "href": format!("https://hcb.hackclub.com/api/v3/transfers/{}", id),
+            "transaction": {
+                "id": format!("txn_{}", id),
+                "object": "transaction",
+                "href": format!("https://hcb.hackclub.com/api/v3/transactions/txn_{}", id),
+                "amount_cents": "-2500",
+                "memo": memo,
+                "date": "2024-03-01",
+                "type": "transfer",
+                "pending": false,
+            },
+            "organization": {
+                "id": "org_onboard",
+                "object": "organization",
+                "href": "https://hcb.hackclub.com/api/v3/organizations/org_onboard",
+            },
+            "to": { "id": "org_recipient", "object": "organization", "name": "Recipient" },
+            "amount_cents": 2500,
+            "memo": "pcb",
+            "date": "2024-03-01T17:04:12Z",
+            "status": "completed",
+        }))
+        .expect("HCB transfer deserializes")
+    }
+
+    #[test]
+    fn transfer_decodes_a_captured_hcb_transfer() {
+        let transfer = hcb_transfer("xfr_1", "OnBoard grant [Arcade]");
+        assert_eq!(transfer.id, "xfr_1");
+        assert_eq!(transfer.amount_cents, 2500);
+        assert_eq!(transfer.memo.as_deref(), Some("pcb"));
+        assert_eq!(transfer.date, Some(at("2024-03-01T17:04:12Z")));
+        assert_eq!(transfer.status.as_deref(), Some("completed"));
+        assert_eq!(transfer.organization.unwrap().id, "org_onboard");
+        assert_eq!(transfer.recipient.unwrap().id, "org_recipient");
+
+        let transaction = transfer.transaction;
+        assert_eq!(transaction.id, "txn_xfr_1");
+        // Money leaving the org, so the ledger side is negative
+        assert_eq!(transaction.amount_cents, -2500);
+        assert_eq!(transaction.memo, "OnBoard grant [Arcade]");
+        assert_eq!(transaction.date.as_deref(), Some("2024-03-01"));
+        assert_eq!(transaction.type_, "transfer");
+        assert_eq!(transaction.pending, Some(false));
+    }
+
+    #[test]
+    fn transfer_decodes_without_the_optional_fields() {
+        let transfer: Transfer = serde_json::from_value(json!({
+            "id": "xfr_2",
+            "object": "transfer",
+            "href": "https://hcb.hackclub.com/api/v3/transfers/xfr_2",
+            "transaction": {
+                "id": "txn_xfr_2",
+                "object": "transaction",
+                "href": "https://hcb.hackclub.com/api/v3/transactions/txn_xfr_2",
+                "amount_cents": -1000,
+                "memo": "OnBoard grant",
+                "type": "transfer",
+            },
+            "amount_cents": 1000,
+        }))
+        .expect("HCB transfer deserializes");
+        assert_eq!(transfer.amount_cents, 1000);
+        assert!(transfer.organization.is_none());
+        assert!(transfer.recipient.is_none());
+        assert!(transfer.memo.is_none());
+        assert!(transfer.date.is_none());
+        assert!(transfer.status.is_none());
+        assert_eq!(transfer.transaction.amount_cents, -1000);
+        assert!(transfer.transaction.date.is_none());
+        assert!(transfer.transaction.pending.is_none());
+    }
+
+    #[test]
+    fn count_transfers_by_category_decodes_hcb_memos() {
+        let transfers = vec![
+            hcb_transfer("xfr_1", "OnBoard grant for pcb-clock"),
+            hcb_transfer("xfr_2", "onboard grant [Arcade]"),
+            hcb_transfer("xfr_3", "Grant to the robotics club"),
+            hcb_transfer("xfr_4", "Reimbursement for shipping"),
+            hcb_transfer("xfr_5", "REFUND: duplicate order"),
+            hcb_transfer("xfr_6", "Pizza for the hackathon"),
+            hcb_transfer("xfr_7", ""),
+        ];
+        let categories = count_transfers_by_category(&transfers);
+        assert_eq!(categories.len(), 5);
+        assert_eq!(categories["onboard"], 2);
+        assert_eq!(categories["grant"], 1);
+        assert_eq!(categories["reimbursement"], 1);
+        assert_eq!(categories["refund"], 1);
+        assert_eq!(categories["uncategorized"], 2);
+    }
+
+    #[test]
+    fn transfer_program_is_the_first_tag() {
+        assert_eq!(
+            transfer_program("OnBoard grant [Arcade]"),
+            Some("arcade".to_string())
+        );
+        assert_eq!(
+            transfer_program("[ Summer ] [Arcade]"),
+            Some("summer".to_string())
+        );
+        assert_eq!(transfer_program("OnBoard grant"), None);
+        assert_eq!(transfer_program("Empty tag [ ]"), None);
+        assert_eq!(transfer_program("Unterminated [tag"), None);
+    }
+
+    #[test]
+    fn distinct_recipients_counts_unknown_once() {
+        let mut transfers = vec![transfer(1000, "a"), transfer(1000, "b")];
+        for id in ["org_a", "org_a", "org_b"] {
+            let mut transfer = transfer(1000, "c");
+            transfer.recipient = Some(Recipient { id: id.to_string() });
+            transfers.push(transfer);
+        }
+        assert_eq!(distinct_recipients(&transfers), 3);
+        assert_eq!(distinct_recipients(&[]), 0);
+    }
+
+    #[test]
+    fn distinct_programs_ignores_case_and_counts_untagged() {
+        let transfers = vec![
+            transfer(1000, "Grant [Arcade]"),
+            transfer(1000, "Grant [arcade]"),
+            transfer(1000, "Grant [Summer]"),
+            transfer(1000, "Grant"),
+        ];
+        assert_eq!(distinct_programs(&transfers), (2, 1));
+    }
+
+    #[test]
+    fn count_transfers_goes_past_u16() {
+        let transfers = vec![transfer(1000, "OnBoard grant"); 70_000];
+        assert_eq!(count_transfers(&Ok(transfers)), 70_000);
+        assert_eq!(count_transfers(&Err(anyhow::anyhow!("down"))), 0);
+    }
+
+    #[test]
+    fn count_transfers_since_skips_future_and_undated() {
+        let now = at("2024-06-15T12:00:00Z");
+        let transfers = vec![
+            dated("2024-06-14"),
+            dated("2024-06-01"),
+            dated("2024-07-01"),
+            transfer(1000, "undated"),
+        ];
+        assert_eq!(
+            count_transfers_since(&transfers, now, chrono::Duration::days(7)),
+            1
+        );
+        assert_eq!(
+            count_transfers_since(&transfers, now, chrono::Duration::days(30)),
+            2
+        );
+        assert_eq!(grants_per_day(&transfers, now, 0), 0.0);
+    }
+
+    #[test]
+    fn grants_per_day_averages_the_window() {
+        let now = at("2024-06-15T12:00:00Z");
+        let transfers = vec![
+            dated("2024-06-14"),
+            dated("2024-06-01"),
+            dated("2024-05-20"),
+            // Exactly 30 days back is still in
+            dated("2024-05-16T12:00:00Z"),
+            dated("2024-05-16T11:59:59Z"),
+            dated("2024-04-01"),
+            dated("2024-07-01"),
+            transfer(1000, "undated"),
+        ];
+        assert_eq!(grants_per_day(&transfers, now, 30), 4.0 / 30.0);
+        assert_eq!(grants_per_day(&transfers, now, 7), 1.0 / 7.0);
+        assert_eq!(grants_per_day(&[], now, 30), 0.0);
+    }
+
+    #[test]
+    fn trailing_months_cross_the_year() {
+        assert_eq!(
+            trailing_months(at("2024-02-29T23:00:00Z"), 3),
+            vec!["2023-12", "2024-01", "2024-02"]
+        );
+    }
+
+    #[test]
+    fn count_transfers_by_month_fills_empty_months() {
+        let transfers = vec![
+            dated("2024-03-01"),
+            dated("2024-03-09"),
+            dated("2024-01-31"),
+            dated("2023-12-31"),
+            transfer(1000, "undated"),
+        ];
+        assert_eq!(
+            count_transfers_by_month(&transfers, at("2024-03-10T00:00:00Z"), 3),
+            vec![
+                ("2024-01".to_string(), 1),
+                ("2024-02".to_string(), 0),
+                ("2024-03".to_string(), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn approval_ratio_of_nothing_is_zero() {
+        assert_eq!(approval_ratio(3, 1), 0.75);
+        assert_eq!(approval_ratio(0, 0), 0.0);
+    }
+
+    #[test]
+    fn mean_p50_p90_uses_nearest_rank() {
+        assert_eq!(mean_p50_p90(&mut []), None);
+        let mut values = vec![4.0, 1.0, 3.0, 2.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0];
+        assert_eq!(mean_p50_p90(&mut values), Some((5.5, 5.0, 9.0)));
+        assert_eq!(mean_p50_p90(&mut [42.0]), Some((42.0, 42.0, 42.0)));
+    }
+
+    #[test]
+    fn failure_streaks_count_cycles_not_failures() {
+        let gauge = test_gauge("consecutive_scrape_failures");
+        let mut streaks = FailureStreaks::new(gauge.clone(), &["github", "hcb"]);
+
+        streaks.record_failure("github");
+        streaks.record_failure("github");
+        assert!(streaks.has_failed("github"));
+        assert!(!streaks.has_failed("hcb"));
+        assert!(!streaks.finish_cycle());
+        assert_eq!(gauge.with_label_values(&["github"]).get(), 1);
+        assert_eq!(gauge.with_label_values(&["hcb"]).get(), 0);
+
+        streaks.record_failure("github");
+        assert!(!streaks.finish_cycle());
+        assert_eq!(gauge.with_label_values(&["github"]).get(), 2);
+
+        assert!(streaks.finish_cycle());
+        assert!(!streaks.has_failed("github"));
+        assert_eq!(gauge.with_label_values(&["github"]).get(), 0);
+    }
+
+    #[test]
+    fn seen_transfers_only_count_new_ids() {
+        let counter =
+            IntCounterVec::new(Opts::new("transfers_total", "test counter"), &["org"]).unwrap();
+        let mut seen = SeenTransfers::new(counter.clone());
+        let first = vec![transfer(1000, "a"), transfer(2000, "b")];
+
+        assert_eq!(seen.observe("onboard", &first).len(), 2);
+        assert_eq!(counter.with_label_values(&["onboard"]).get(), 2);
+
+        // A re-fetch of the same transfers adds nothing
+        assert!(seen.observe("onboard", &first).is_empty());
+        assert_eq!(counter.with_label_values(&["onboard"]).get(), 2);
+
+        let refetched = vec![
+            transfer(1000, "a"),
+            transfer(2000, "b"),
+            transfer(3000, "c"),
+        ];
+        let new = seen.observe("onboard", &refetched);
+        assert_eq!(new.len(), 1);
+        assert_eq!(new[0].id, "xfr_3000");
+        assert_eq!(counter.with_label_values(&["onboard"]).get(), 3);
+
+        // The same id in another org is a different transfer
+        assert_eq!(seen.observe("arcade", &first).len(), 2);
+        assert_eq!(counter.with_label_values(&["arcade"]).get(), 2);
+        assert_eq!(counter.with_label_values(&["onboard"]).get(), 3);
+    }
+
+    /// A raw transfer with both amounts set to `amount`, as HCB would send it
+    fn raw_transfer(amount: serde_json::Value) -> serde_json::Value {
+        json!({
+            "id": "xfr",
+            "object": "transfer",
+            "href": "https://hcb.hackclub.com/api/v3/transfers/xfr",
+            "transaction": {
+                "id": "txn",
+                "object": "transaction",
+                "href": "https://hcb.hackclub.com/api/v3/transactions/txn",
+                "amount_cents": amount,
+                "memo": "OnBoard grant",
+                "type": "transfer",
+            },
+            "amount_cents": amount,
+        })
+    }
+
+    #[test]
+    fn amount_cents_accepts_numbers_and_strings() {
+        for amount in [json!(1234), json!("1234"), json!(" 1234 ")] {
+            let transfer: Transfer = serde_json::from_value(raw_transfer(amount.clone()))
+                .unwrap_or_else(|e| panic!("{} should parse: {}", amount, e));
+            assert_eq!(transfer.amount_cents, 1234);
+            assert_eq!(transfer.transaction.amount_cents, 1234);
+        }
+
+        // Ledger transactions can go negative, transfers can't
+        let mut raw = raw_transfer(json!(500));
+        raw["transaction"]["amount_cents"] = json!("-500");
+        let transfer: Transfer = serde_json::from_value(raw).unwrap();
+        assert_eq!(transfer.transaction.amount_cents, -500);
+    }
+
+    #[test]
+    fn amount_cents_rejects_bad_strings() {
+        for amount in [
+            json!("12.34"),
+            json!("1,234"),
+            json!("lots"),
+            json!(""),
+            json!("-5"),
+        ] {
+            let err = serde_json::from_value::<Transfer>(raw_transfer(amount.clone()))
+                .err()
+                .unwrap_or_else(|| panic!("{} should be rejected", amount));
+            assert!(err.to_string().contains("invalid amount"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn circuit_breaker_opens_after_the_threshold() {
+        let gauge = test_gauge("circuit_open");
+        let mut breaker =
+            CircuitBreaker::new(gauge.clone(), 2, Duration::from_secs(3600), &["hcb"]);
+
+        breaker.record("hcb", false);
+        assert!(breaker.allows("hcb"));
+        assert_eq!(gauge.with_label_values(&["hcb"]).get(), 0);
+
+        breaker.record("hcb", false);
+        assert!(!breaker.allows("hcb"));
+        assert_eq!(gauge.with_label_values(&["hcb"]).get(), 1);
+        assert!(breaker.allows("github"));
+    }
+
+    #[test]
+    fn circuit_breaker_skipped_source_serves_stale_values() {
+        let gauge = test_gauge("circuit_open");
+        let mut breaker =
+            CircuitBreaker::new(gauge.clone(), 1, Duration::from_secs(3600), &["hcb"]);
+        let total = GaugeVec::new(Opts::new("total_grants_dollars", "test"), &["org"]).unwrap();
+        let total_stale = test_gauge("total_grants_dollars_stale");
+        let mut state = MetricsState::new();
+        state.set_fresh(&total, &total_stale, &["onboard"], 120.0);
+
+        breaker.record("hcb", false);
+        assert!(!breaker.allows("hcb"));
+        // What main does for a source it skips
+        state.set_all_stale(&total, &total_stale, [vec!["onboard"], vec!["arcade"]]);
+
+        assert_eq!(total.with_label_values(&["onboard"]).get(), 120.0);
+        assert_eq!(total_stale.with_label_values(&["onboard"]).get(), 1);
+        // Never fetched, so there's no value to keep, only the flag
+        assert_eq!(total.with_label_values(&["arcade"]).get(), 0.0);
+        assert_eq!(total_stale.with_label_values(&["arcade"]).get(), 1);
+    }
+
+    #[test]
+    fn circuit_breaker_closes_when_the_trial_succeeds() {
+        let gauge = test_gauge("circuit_open");
+        let cooldown = Duration::from_millis(50);
+        let mut breaker = CircuitBreaker::new(gauge.clone(), 1, cooldown, &["hcb"]);
+
+        breaker.record("hcb", false);
+        assert!(!breaker.allows("hcb"));
+
+        // Half-open: once cooled down a single trial is let through
+        std::thread::sleep(cooldown * 2);
+        assert!(breaker.allows("hcb"));
+        assert_eq!(gauge.with_label_values(&["hcb"]).get(), 1);
+
+        breaker.record("hcb", true);
+        assert!(breaker.allows("hcb"));
+        assert_eq!(gauge.with_label_values(&["hcb"]).get(), 0);
+    }
+
+    #[test]
+    fn circuit_breaker_reopens_when_the_trial_fails() {
+        let gauge = test_gauge("circuit_open");
+        let cooldown = Duration::from_millis(50);
+        let mut breaker = CircuitBreaker::new(gauge.clone(), 3, cooldown, &["hcb"]);
+
+        for _ in 0..3 {
+            breaker.record("hcb", false);
+        }
+        assert!(!breaker.allows("hcb"));
+
+        std::thread::sleep(cooldown * 2);
+        assert!(breaker.allows("hcb"));
+
+        // A single failed trial is enough, no need to reach the threshold again
+        breaker.record("hcb", false);
+        assert!(!breaker.allows("hcb"));
+        assert_eq!(gauge.with_label_values(&["hcb"]).get(), 1);
+    }
+
+    #[test]
+    fn circuit_breaker_without_a_cooldown_never_opens() {
+        let gauge = test_gauge("circuit_open");
+        let mut breaker = CircuitBreaker::new(gauge.clone(), 1, Duration::ZERO, &["hcb"]);
+
+        for _ in 0..5 {
+            breaker.record("hcb", false);
+        }
+        assert!(breaker.allows("hcb"));
+        assert_eq!(gauge.with_label_values(&["hcb"]).get(), 0);
+    }
+
+    #[test]
+    fn count_onboard_prs_filters_by_label() {
+        let prs: Vec<PullRequest> = serde_json::from_value(json!([
+            { "number": 1, "assignees": [], "labels": [{ "name": "Submission" }],
+              "requested_reviewers": [], "state": "open", "merged_at": null },
+            { "number": 2, "assignees": [], "labels": [{ "name": "Submission" }],
+              "requested_reviewers": [], "state": "closed", "merged_at": "2024-01-01T00:00:00Z" },
+            { "number": 3, "assignees": [], "labels": [],
+              "requested_reviewers": [], "state": "open", "merged_at": null },
+            { "number": 4, "assignees": [], "labels": [{ "name": "Submission" }],
+              "requested_reviewers": [], "state": "closed", "merged_at": null },
+        ]))
+        .unwrap();
+        let prs: Vec<PullRequest> = prs.into_iter().map(PullRequest::is_merged).collect();
+
+        assert_eq!(count_onboard_prs(&prs, None), (2, 1));
+        assert_eq!(count_onboard_prs(&prs, Some("Submission")), (1, 1));
+        assert_eq!(count_onboard_prs(&prs, Some("Dev")), (0, 0));
+    }
+
+    #[test]
+    fn airtable_response_decodes_a_page() {
+        let page: AirtableResponse = serde_json::from_value(json!({
+            "records": [
+                { "id": "rec1", "createdTime": "2024-03-01T10:00:00.000Z",
+                  "fields": { "Approved At": "2024-03-02T10:00:00.000Z" } },
+                { "id": "rec2", "createdTime": "2024-03-01T11:00:00.000Z" },
+            ],
+            "offset": "itrNext/rec2",
+        }))
+        .unwrap();
+        assert_eq!(page.records.len(), 2);
+        assert_eq!(page.records[0].fields.len(), 1);
+        assert!(page.records[1].fields.is_empty());
+        assert_eq!(page.offset.as_deref(), Some("itrNext/rec2"));
+
+        let last: AirtableResponse = serde_json::from_value(json!({ "records": [] })).unwrap();
+        assert!(last.offset.is_none());
+
+        assert!(serde_json::from_value::<AirtableResponse>(json!({ "records": "none" })).is_err());
+    }
+
+    #[test]
+    fn metrics_state_goes_stale_and_back() {
+        let gauge = IntGaugeVec::new(Opts::new("records", "test gauge"), &["view"]).unwrap();
+        let stale = IntGaugeVec::new(Opts::new("records_stale", "test gauge"), &["view"]).unwrap();
+        let mut state = MetricsState::new();
+
+        // Nothing to fall back to yet, only the flag is set
+        state.set_stale(&gauge, &stale, &["Approved"]);
+        assert_eq!(gauge.with_label_values(&["Approved"]).get(), 0);
+        assert_eq!(stale.with_label_values(&["Approved"]).get(), 1);
+
+        state.set_fresh(&gauge, &stale, &["Approved"], 12.0);
+        assert_eq!(gauge.with_label_values(&["Approved"]).get(), 12);
+        assert_eq!(stale.with_label_values(&["Approved"]).get(), 0);
+
+        // Something zeroed the series in between, the last good value comes back
+        gauge.with_label_values(&["Approved"]).set(0);
+        state.set_stale(&gauge, &stale, &["Approved"]);
+        assert_eq!(gauge.with_label_values(&["Approved"]).get(), 12);
+        assert_eq!(stale.with_label_values(&["Approved"]).get(), 1);
+
+        state.set_fresh(&gauge, &stale, &["Approved"], 15.0);
+        assert_eq!(gauge.with_label_values(&["Approved"]).get(), 15);
+        assert_eq!(stale.with_label_values(&["Approved"]).get(), 0);
+    }
+
+    #[test]
+    fn metrics_state_handles_plain_gauges() {
+        let gauge = IntGauge::new("waiting", "test gauge").unwrap();
+        let stale = IntGauge::new("waiting_stale", "test gauge").unwrap();
+        let mut state = MetricsState::new();
+
+        state.set_fresh(&gauge, &stale, &[], 4.0);
+        gauge.set(0);
+        state.set_stale(&gauge, &stale, &[]);
+        assert_eq!(gauge.get(), 4);
+        assert_eq!(stale.get(), 1);
+    }
+
+    #[test]
+    fn metrics_state_delta_is_per_name() {
+        let mut state = MetricsState::new();
+        assert_eq!(state.delta("submitted_projects", 5.0), 0.0);
+        assert_eq!(state.delta("submitted_projects", 8.0), 3.0);
+        assert_eq!(state.delta("other", 1.0), 0.0);
+        assert_eq!(state.delta("submitted_projects", 6.0), -2.0);
+    }
+
+    /// A projects tree in memory, each path maps to the file's contents or
+    /// `None` for a directory. Every directory has to be listed.
+    struct MemoryTree(BTreeMap<PathBuf, Option<String>>);
+
+    impl MemoryTree {
+        fn new(entries: &[(&str, Option<&str>)]) -> MemoryTree {
+            MemoryTree(
+                entries
+                    .iter()
+                    .map(|(path, contents)| (PathBuf::from(path), contents.map(String::from)))
+                    .collect(),
+            )
+        }
+
+        fn children(&self, dir: &Path, dirs_only: bool) -> anyhow::Result<Vec<String>> {
+            if !dir.as_os_str().is_empty() && self.0.get(dir) != Some(&None) {
+                anyhow::bail!("{} is not a directory", dir.display());
+            }
+            Ok(self
+                .0
+                .iter()
+                .filter(|(path, contents)| {
+                    path.parent() == Some(dir) && (!dirs_only || contents.is_none())
+                })
+                .map(|(path, _)| path.file_name().unwrap().to_string_lossy().into_owned())
+                .collect())
+        }
+    }
+
+    impl ProjectSource for MemoryTree {
+        fn dirs(&self, dir: &Path) -> anyhow::Result<Vec<String>> {
+            self.children(dir, true)
+        }
+
+        fn entries(&self, dir: &Path) -> anyhow::Result<Vec<String>> {
+            self.children(dir, false)
+        }
+
+        fn read_file(&self, path: &Path) -> Option<String> {
+            self.0.get(path).cloned().flatten()
+        }
+    }
+
+    #[test]
+    fn count_projects_skips_hidden_dirs_and_files() {
+        let tree = MemoryTree::new(&[
+            ("alpha", None),
+            ("beta", None),
+            (".github", None),
+            ("README.md", Some("# Projects")),
+        ]);
+        assert_eq!(count_projects(&tree).unwrap(), 2.0);
+        assert_eq!(count_projects(&MemoryTree::new(&[])).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn count_project_categories_counts_one_level_down() {
+        let tree = MemoryTree::new(&[
+            ("games", None),
+            ("games/pong", None),
+            ("games/snake", None),
+            ("games/.assets", None),
+            ("games/index.md", Some("")),
+            ("tools", None),
+            ("tools/cli", None),
+            (".github", None),
+            (".github/workflows", None),
+        ]);
+        let categories = count_project_categories(&tree);
+        assert_eq!(categories.len(), 2);
+        assert_eq!(categories["games"], 2.0);
+        assert_eq!(categories["tools"], 1.0);
+    }
+
+    #[test]
+    fn count_projects_by_readme_matches_any_case() {
+        let tree = MemoryTree::new(&[
+            ("alpha", None),
+            ("alpha/Readme.md", Some("")),
+            ("beta", None),
+            ("beta/README", Some("")),
+            ("gamma", None),
+            ("gamma/main.c", Some("")),
+            ("delta", None),
+        ]);
+        let by_readme = count_projects_by_readme(&tree);
+        assert_eq!(by_readme["true"], 2.0);
+        assert_eq!(by_readme["false"], 2.0);
+        assert_eq!(by_readme["unknown"], 0.0);
+    }
+
+    #[test]
+    fn sum_requested_dollars_counts_missing_and_broken_manifests() {
+        let tree = MemoryTree::new(&[
+            ("alpha", None),
+            ("alpha/project.yml", Some("requested_dollars: 50")),
+            ("beta", None),
+            (
+                "beta/project.yaml",
+                Some("requested_dollars: 25.5\nname: beta"),
+            ),
+            ("gamma", None),
+            ("gamma/project.yml", Some("requested_dollars: [")),
+            ("delta", None),
+        ]);
+        assert_eq!(sum_requested_dollars(&tree), Some((75.5, 2)));
+    }
+}
@@ -1,471 +1,2542 @@
 // This program creates a Prometheus exporter with a single metric that tracks
 // the number of directories in the specified projects folder.
 
+use anyhow::Context;
+use chrono::Utc;
+use clap::{builder::FalseyValueParser, Parser};
 use env_logger::{Builder, Env};
-use log::info;
-use prometheus_exporter::prometheus::{
-    register_gauge, register_int_gauge, register_int_gauge_vec, Opts,
-};
-use reqwest::{
-    header::{HeaderMap, HeaderValue, AUTHORIZATION},
-    Client, Url,
-};
+use flate2::{write::GzEncoder, Compression};
+use log::{debug, error, info, warn};
+use prometheus_exporter::prometheus;
+use prometheus_exporter::prometheus::{Encoder, TextEncoder};
+use rand::Rng;
+use reqwest::{header::HeaderValue, Client, ClientBuilder, Identity, NoProxy, Proxy, Url};
 use std::fs;
+use std::io::Write;
 use std::net::SocketAddr;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{collections::HashMap, env};
+use tokio::signal::unix::{signal, SignalKind};
 
 mod lib;
 use lib::*;
 
 #[tokio::main]
 async fn main() {
-    // Set up logger with default level info so we can see the messages from
-    // prometheus_exporter.
-    Builder::from_env(Env::default().default_filter_or("info")).init();
+    let args = Args::parse();
+    init_logger(args.log_format == "json");
 
-    let addr_raw = "0.0.0.0:8521";
-    let addr: SocketAddr = addr_raw.parse().expect("Cannot parse listen address");
+    let config = match Config::from_args(args) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Invalid configuration: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    config.log();
 
-    let airtable_api: Result<String, env::VarError> = env::var("AIRTABLE_API");
-    let raw_github_api_key: Option<String> = env::var("GITHUB_API").ok();
+    let mut repo_caches: HashMap<String, Option<RepoCache>> = HashMap::new();
+    let mut hcb_cache: HashMap<String, HcbCache> = HashMap::new();
 
-    let opts = Opts::new(
-        "pr_reviewer_stats",
-        "Number of pull requests reviewed by each reviewer",
-    );
-    let counter_vec =
-        register_int_gauge_vec!(opts, &["reviewer"]).expect("Failed to create counter vector");
+    // One client for every upstream so connections and TLS sessions are reused
+    let client = build_client(&config);
+
+    if config.validate_only {
+        let all_ok = validate_config(&config, &client).await;
+        std::process::exit(if all_ok { 0 } else { 1 });
+    }
 
-    let stats_merged = Opts::new(
-        "pr_reviewer_stats_merged",
-        "Number of pull requests merged reviewed by each reviewer",
+    let registry = prometheus::default_registry();
+    let metrics = register_metrics(
+        registry,
+        &config.static_labels,
+        &config.grant_histogram_buckets,
+    );
+    for (source, enabled) in [
+        ("github", config.enable_github),
+        ("airtable", config.enable_airtable),
+        ("hcb", config.enable_hcb),
+    ] {
+        if !enabled {
+            metrics.unregister_source(registry, source);
+        }
+    }
+    let Metrics {
+        build_info,
+        refresh_interval_seconds,
+        pr_reviewer_stats: counter_vec,
+        pr_reviewer_stats_merged,
+        pr_merger_stats,
+        pr_review_stats,
+        submitted_projects,
+        total_submitted_projects,
+        submitted_projects_stale,
+        submitted_projects_delta,
+        submitted_projects_by_category,
+        submitted_projects_by_readme,
+        projects_requested_dollars_total,
+        projects_missing_manifest,
+        git: git_metrics,
+        transfers_count,
+        transfers_count_by_month,
+        avg_grant: average_grant_value,
+        avg_grant_smoothed,
+        median_grant: median_grant_value,
+        max_grant: max_grant_value,
+        grant_range_dollars,
+        grants_per_day: grants_per_day_rate,
+        total_grants_dollars,
+        avg_grant_stale,
+        median_grant_stale,
+        max_grant_stale,
+        total_grants_dollars_stale,
+        oldest_transfer_timestamp,
+        newest_transfer_timestamp,
+        distinct_recipients: distinct_recipients_count,
+        distinct_programs: distinct_programs_count,
+        transfers_unknown_program,
+        transfers_by_category,
+        grant_amount_dollars: grant_amounts,
+        transfers_total,
+        airtable_records,
+        airtable_records_stale,
+        airtable: airtable_metrics,
+        airtable_approval_seconds,
+        airtable_approval_ratio,
+        waiting_review,
+        waiting_review_stale,
+        onboard_prs,
+        onboard_prs_stale,
+        hcb: hcb_metrics,
+        github_rate_limit_remaining,
+        scrape_duration_seconds: scrape_duration,
+        scrape_errors_total: scrape_errors,
+        last_successful_scrape_timestamp: last_successful_scrape,
+        consecutive_scrape_failures,
+        upstreams_reachable,
+        circuit_open,
+    } = metrics;
+
+    build_info
+        .with_label_values(&[env!("CARGO_PKG_VERSION"), env!("GIT_SHA")])
+        .set(1);
+    refresh_interval_seconds.set(config.refresh_interval.as_secs_f64());
+
+    let mut avg_grant_ema: HashMap<String, f64> = HashMap::new();
+    // Transfers already observed into the histogram and counter, so re-fetches
+    // don't double count
+    let mut seen_transfers = SeenTransfers::new(transfers_total);
+    // Last good HCB grant stats, put back while an org's fetch is failing, and
+    // the previous project count for submitted_projects_delta
+    let mut metrics_state = MetricsState::new();
+
+    let enabled_sources: Vec<&'static str> = [
+        ("github", config.enable_github),
+        ("airtable", config.enable_airtable),
+        ("hcb", config.enable_hcb),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(source, _)| source)
+    .collect();
+
+    let mut scrape_failures = FailureStreaks::new(consecutive_scrape_failures, &enabled_sources);
+    let mut circuit_breaker = CircuitBreaker::new(
+        circuit_open,
+        config.circuit_failure_threshold,
+        config.circuit_cooldown,
+        &enabled_sources,
     );
-    let pr_reviewer_stats_merged = register_int_gauge_vec!(stats_merged, &["reviewer"])
-        .expect("Failed to create counter vector");
 
-    let submitted_projects = register_gauge!(
-        "submitted_projects",
-        "Number of folders in the projects directory in the OnBoard Github"
-    )
-    .expect("Cannot create gauge onboard_grants_given");
+    let health = Arc::new(HealthState::default());
+    // Held while the gauges are updated, and by /metrics while encoding them
+    let update_lock = Arc::new(Mutex::new(()));
+    if !config.oneshot {
+        spawn_health_server(config.health_addr, health.clone());
+        spawn_metrics_server(config.addr, update_lock.clone()).expect("Cannot start exporter");
+    }
 
-    let transfers_count = register_int_gauge!(
-        "transfers_count",
-        "Grant transfers out of the OnBoard Hack Club Bank"
-    )
-    .expect("Cannot create gauge transfers_count");
+    let mut last_slack_post: Option<Instant> = None;
 
-    // Create the metric
-    let average_grant_value = register_gauge!("avg_grant", "Average dollars given per grant")
-        .expect("Cannot create gauge average_grant_value");
+    // First refresh right away, later ones an interval (give or take the
+    // jitter) after the previous one started
+    let mut next_refresh = tokio::time::Instant::now();
 
-    let airtable_records_approved_metric =
-        register_int_gauge!("airtable_records", "Number of Approved Airtable Records")
-            .expect("Cannot create gauge airtable_records_approved_metric");
+    // Installed up front so a signal arriving mid-refresh is picked up as soon
+    // as the cycle finishes instead of killing us halfway through a clone
+    let mut sigterm = signal(SignalKind::terminate()).expect("Cannot install SIGTERM handler");
+    let mut sigint = signal(SignalKind::interrupt()).expect("Cannot install SIGINT handler");
 
-    let airtable_records_pending_metric = register_int_gauge!(
-        "airtable_records_pending",
-        "Number of Pending Airtable Records"
-    )
-    .expect("Cannot create gauge airtable_records_pending_metric");
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep_until(next_refresh) => {}
+            _ = sigterm.recv() => {
+                info!("Received SIGTERM, shutting down");
+                break;
+            }
+            _ = sigint.recv() => {
+                info!("Received SIGINT, shutting down");
+                break;
+            }
+        }
 
-    let waiting_review =
-        register_int_gauge!("waiting_review", "Number of Pull Requests waiting a review")
-            .expect("Cannot create gauge airtable_records_pending_metric");
+        info!("Refreshing metrics");
+        let cycle_started = tokio::time::Instant::now();
+        health.last_cycle_completed.store(false, Ordering::SeqCst);
 
-    let mut prs = fetch_pull_requests(raw_github_api_key.clone()).await;
-    let mut hcb_data = fetch_hcb().await;
+        // Sources whose circuit is open keep their last values and count as
+        // failed, without being asked again until the cooldown is over
+        let mut fetched_sources = Vec::new();
+        for source in &enabled_sources {
+            if circuit_breaker.allows(source) {
+                fetched_sources.push(*source);
+            } else {
+                info!("Circuit open, skipping {} this refresh", source);
+                scrape_failures.record_failure(source);
+            }
+        }
+        let fetch_github = fetched_sources.contains(&"github");
+        let fetch_airtable = fetched_sources.contains(&"airtable");
+        let fetch_hcb = fetched_sources.contains(&"hcb");
+
+        // Fetch everything before touching the gauges so scrapes in the
+        // meantime keep serving the previous, complete set of values. The
+        // sources are independent, so they're fetched concurrently and each
+        // one deals with its own errors.
+        let github = async {
+            if !fetch_github {
+                return (Vec::new(), ProjectBreakdown::default(), None, None);
+            }
+            let started = Instant::now();
+            let mut project_counts = Vec::new();
+            let mut project_breakdown = ProjectBreakdown::default();
+            // One at a time, the clones share a scratch checkout
+            for (index, repo) in config.onboard_repos.iter().enumerate() {
+                // Only the first repo is broken down by category, README and manifest
+                let count = count_dirs(
+                    repo_caches.entry(repo.url.clone()).or_default(),
+                    repo,
+                    &config,
+                    index == 0,
+                    &git_metrics,
+                )
+                .await
+                .map(|(count, breakdown)| {
+                    if let Some(breakdown) = breakdown {
+                        project_breakdown = breakdown;
+                    }
+                    count
+                });
+                project_counts.push((repo.label.clone(), count));
+            }
+            let prs = fetch_pull_requests(
+                &client,
+                &config.github_api_url,
+                config.github_api_key.clone(),
+                // The same HTTP_MAX_RETRIES as every other source
+                config.hcb.max_retries,
+                &github_rate_limit_remaining,
+            )
+            .await;
+            // Without a token the stats are skipped, which isn't a failure
+            let pr_stats = match config
+                .github_token
+                .clone()
+                .or_else(|| config.github_api_key.clone())
+            {
+                Some(token) => Some(
+                    github_pr_stats(
+                        &client,
+                        &config.github_api_url,
+                        Some(token),
+                        &github_rate_limit_remaining,
+                    )
+                    .await,
+                ),
+                None => {
+                    info!(target: "github", "No GitHub token, skipping the GraphQL PR stats");
+                    None
+                }
+            };
+            scrape_duration
+                .with_label_values(&["github"])
+                .observe(started.elapsed().as_secs_f64());
+            (project_counts, project_breakdown, Some(prs), pr_stats)
+        };
+        let airtable = async {
+            if !fetch_airtable {
+                return (Vec::new(), None, None);
+            }
+            let started = Instant::now();
+            let airtable_data =
+                airtable_records_by_view(&client, &config.airtable, &airtable_metrics).await;
+            let airtable_custom = match &config.airtable.filter_formula {
+                Some(formula) => Some(
+                    airtable_verifications(
+                        &client,
+                        &config.airtable,
+                        &airtable_metrics,
+                        &AirtableQuery {
+                            view: None,
+                            filter_formula: Some(formula.as_str()),
+                            fields: &[],
+                        },
+                    )
+                    .await,
+                ),
+                None => None,
+            };
+            let approval_times = match &config.airtable.approved_field {
+                Some(approved_field) => {
+                    let submitted_field = config.airtable.submitted_field.as_deref();
+                    let fields: Vec<&str> = submitted_field
+                        .into_iter()
+                        .chain([approved_field.as_str()])
+                        .collect();
+                    Some(
+                        airtable_records(
+                            &client,
+                            &config.airtable,
+                            &airtable_metrics,
+                            &AirtableQuery {
+                                view: Some("Approved"),
+                                filter_formula: None,
+                                fields: &fields,
+                            },
+                        )
+                        .await
+                        .map(|records| {
+                            approval_durations(&records, submitted_field, approved_field)
+                        }),
+                    )
+                }
+                None => None,
+            };
+            scrape_duration
+                .with_label_values(&["airtable"])
+                .observe(started.elapsed().as_secs_f64());
+            (airtable_data, airtable_custom, approval_times)
+        };
+        let hcb = async {
+            if !fetch_hcb {
+                return (Vec::new(), Vec::new());
+            }
+            let started = Instant::now();
+            let (hcb_data, hcb_totals) = if config.hcb.streaming {
+                let hcb_totals = fetch_hcb_org_totals(&client, &config.hcb, &hcb_metrics).await;
+                (Vec::new(), hcb_totals)
+            } else {
+                let hcb_data =
+                    fetch_hcb_orgs(&client, &config.hcb, &hcb_metrics, &mut hcb_cache).await;
+                (hcb_data, Vec::new())
+            };
+            scrape_duration
+                .with_label_values(&["hcb"])
+                .observe(started.elapsed().as_secs_f64());
+            (hcb_data, hcb_totals)
+        };
+        let (
+            (project_counts, project_breakdown, prs, pr_stats),
+            (airtable_data, airtable_custom, approval_times),
+            (hcb_data, hcb_totals),
+        ) = tokio::join!(github, airtable, hcb);
+        info!(
+            duration_secs = cycle_started.elapsed().as_secs_f64();
+            "Fetched every source in {:?}",
+            cycle_started.elapsed()
+        );
 
-    let exporter = prometheus_exporter::start(addr).expect("Cannot start exporter");
+        // Hold the update lock while swapping the values in, so a scrape
+        // never sees a half-updated set
+        let guard = update_lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        // A source skipped while its circuit is open keeps its last good
+        // values, flagged stale like after any other failed fetch
+        for source in enabled_sources
+            .iter()
+            .filter(|source| !fetched_sources.contains(*source))
+        {
+            match *source {
+                "github" => {
+                    let repos = config
+                        .onboard_repos
+                        .iter()
+                        .map(|repo| vec![repo.label.as_str()]);
+                    metrics_state.set_all_stale(
+                        &submitted_projects,
+                        &submitted_projects_stale,
+                        repos,
+                    );
+                    let states = ["open", "merged"].map(|state| vec![state]);
+                    metrics_state.set_all_stale(&onboard_prs, &onboard_prs_stale, states);
+                    metrics_state.set_stale(&waiting_review, &waiting_review_stale, &[]);
+                }
+                "airtable" => {
+                    let views = config
+                        .airtable
+                        .views
+                        .iter()
+                        .map(|view| vec![view.as_str(), ""]);
+                    let custom = config
+                        .airtable
+                        .filter_formula
+                        .as_ref()
+                        .map(|_| vec!["", "custom"]);
+                    metrics_state.set_all_stale(
+                        &airtable_records,
+                        &airtable_records_stale,
+                        views.chain(custom),
+                    );
+                }
+                "hcb" => {
+                    let orgs = || config.hcb.orgs.iter().map(|org| vec![org.as_str()]);
+                    for (gauge, stale) in [
+                        (&average_grant_value, &avg_grant_stale),
+                        (&max_grant_value, &max_grant_stale),
+                        (&total_grants_dollars, &total_grants_dollars_stale),
+                    ] {
+                        metrics_state.set_all_stale(gauge, stale, orgs());
+                    }
+                    // Only known when every grant is kept
+                    if !config.hcb.streaming {
+                        metrics_state.set_all_stale(
+                            &median_grant_value,
+                            &median_grant_stale,
+                            orgs(),
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
 
-    loop {
-        let _guard = exporter.wait_request();
+        let mut projects_counted = !project_counts.is_empty();
+        for (repo, count) in &project_counts {
+            match count {
+                Ok(count) => {
+                    metrics_state.set_fresh(
+                        &submitted_projects,
+                        &submitted_projects_stale,
+                        &[repo],
+                        *count,
+                    );
+                    info!("New directory count for {}: {}", repo, count);
+                    last_successful_scrape
+                        .with_label_values(&["github"])
+                        .set(unix_now());
+                }
+                Err(e) => {
+                    // Keep the previous count rather than crashing or dropping to 0
+                    warn!("Failed to count project directories of {}: {}", repo, e);
+                    metrics_state.set_stale(
+                        &submitted_projects,
+                        &submitted_projects_stale,
+                        &[repo],
+                    );
+                    scrape_errors.with_label_values(&["github"]).inc();
+                    scrape_failures.record_failure("github");
+                    projects_counted = false;
+                }
+            }
+        }
+        if !project_counts.is_empty() {
+            // Repos that failed this time still add their last good count
+            let total: f64 = config
+                .onboard_repos
+                .iter()
+                .map(|repo| submitted_projects.with_label_values(&[&repo.label]).get())
+                .sum();
+            total_submitted_projects.set(total);
+            // Nothing new is seen while a repo is failing, the next complete
+            // total is compared against the last complete one
+            submitted_projects_delta.set(if projects_counted {
+                metrics_state.delta("submitted_projects", total)
+            } else {
+                0.0
+            });
+        }
+        for (category, count) in project_breakdown.categories {
+            submitted_projects_by_category
+                .with_label_values(&[&category])
+                .set(count);
+        }
+        for (has_readme, count) in project_breakdown.by_readme {
+            submitted_projects_by_readme
+                .with_label_values(&[has_readme])
+                .set(count);
+        }
+        if let Some((requested_dollars, missing_manifest)) = project_breakdown.requested_dollars {
+            projects_requested_dollars_total.set(requested_dollars);
+            projects_missing_manifest.set(missing_manifest);
+        }
 
-        info!("Updating metrics");
+        for (view, view_data) in &airtable_data {
+            match view_data {
+                Ok(count) => {
+                    metrics_state.set_fresh(
+                        &airtable_records,
+                        &airtable_records_stale,
+                        &[view.as_str(), ""],
+                        *count as f64,
+                    );
+                    last_successful_scrape
+                        .with_label_values(&["airtable"])
+                        .set(unix_now());
+                    info!(
+                        "New airtable records {} count: {:?}",
+                        view,
+                        airtable_records.with_label_values(&[view.as_str(), ""])
+                    );
+                }
+                Err(e) => {
+                    warn!("Failed to fetch {} Airtable records: {}", view, e);
+                    metrics_state.set_stale(
+                        &airtable_records,
+                        &airtable_records_stale,
+                        &[view.as_str(), ""],
+                    );
+                    scrape_errors.with_label_values(&["airtable"]).inc();
+                    scrape_failures.record_failure("airtable");
+                }
+            }
+        }
 
-        submitted_projects.set(count_dirs());
-        info!("New directory count: {:?}", submitted_projects);
+        match &airtable_custom {
+            Some(Ok(count)) => {
+                metrics_state.set_fresh(
+                    &airtable_records,
+                    &airtable_records_stale,
+                    &["", "custom"],
+                    *count as f64,
+                );
+                info!("New airtable records custom filter count: {}", count);
+            }
+            Some(Err(e)) => {
+                warn!(
+                    "Failed to fetch Airtable records for the custom filter: {}",
+                    e
+                );
+                metrics_state.set_stale(
+                    &airtable_records,
+                    &airtable_records_stale,
+                    &["", "custom"],
+                );
+                scrape_errors.with_label_values(&["airtable"]).inc();
+                scrape_failures.record_failure("airtable");
+            }
+            None => {}
+        }
 
-        airtable_records_approved_metric.set(
-            airtable_verifications(airtable_api.clone(), AirTableViews::Approved)
-                .await
-                .into(),
-        );
-        info!(
-            "New airtable records approved count: {:?}",
-            airtable_records_approved_metric
-        );
+        match approval_times {
+            Some(Ok(mut durations)) => {
+                if let Some((mean, p50, p90)) = mean_p50_p90(&mut durations) {
+                    for (stat, seconds) in [("mean", mean), ("p50", p50), ("p90", p90)] {
+                        airtable_approval_seconds
+                            .with_label_values(&[stat])
+                            .set(seconds);
+                    }
+                }
+            }
+            Some(Err(e)) => {
+                warn!("Failed to fetch approved Airtable records: {}", e);
+                scrape_errors.with_label_values(&["airtable"]).inc();
+                scrape_failures.record_failure("airtable");
+            }
+            None => {}
+        }
 
-        airtable_records_pending_metric.set(
-            airtable_verifications(airtable_api.clone(), AirTableViews::Pending)
-                .await
-                .into(),
-        );
+        // Needs both views configured, reading a missing one would create its series
+        if config.enable_airtable
+            && config.airtable.views.iter().any(|view| view == "Approved")
+            && config.airtable.views.iter().any(|view| view == "Pending")
+        {
+            airtable_approval_ratio.set(approval_ratio(
+                airtable_records.with_label_values(&["Approved", ""]).get(),
+                airtable_records.with_label_values(&["Pending", ""]).get(),
+            ));
+        }
 
-        info!(
-            "New airtable records pending count: {:?}",
-            airtable_records_pending_metric
-        );
+        match prs {
+            Some(Ok(prs)) => {
+                for (reviewer, count) in parse_reviewer_stats(prs.clone(), State::any) {
+                    counter_vec
+                        .with_label_values(&[&reviewer])
+                        .set(count.into());
+                }
+
+                for (reviewer, count) in parse_reviewer_stats(prs.clone(), State::merged) {
+                    pr_reviewer_stats_merged
+                        .with_label_values(&[&reviewer])
+                        .set(count.into());
+                }
 
-        for (reviewer, count) in parse_reviewer_stats(prs.clone(), State::any) {
-            counter_vec
-                .with_label_values(&[&reviewer])
-                .set(count.into());
+                let (open, merged) = count_onboard_prs(&prs, config.github_pr_label.as_deref());
+                for (state, count) in [("open", open), ("merged", merged)] {
+                    metrics_state.set_fresh(
+                        &onboard_prs,
+                        &onboard_prs_stale,
+                        &[state],
+                        count as f64,
+                    );
+                }
+                info!(target: "github", "OnBoard PRs: {} open, {} merged", open, merged);
+
+                let waiting = awaiting_reviews(prs);
+                metrics_state.set_fresh(
+                    &waiting_review,
+                    &waiting_review_stale,
+                    &[],
+                    waiting.into(),
+                );
+                info!("New waiting review count: {}", waiting);
+            }
+            Some(Err(e)) => {
+                // Keep the previous review counts rather than dropping to 0
+                warn!("Failed to fetch GitHub pull requests: {:#}", e);
+                for state in ["open", "merged"] {
+                    metrics_state.set_stale(&onboard_prs, &onboard_prs_stale, &[state]);
+                }
+                metrics_state.set_stale(&waiting_review, &waiting_review_stale, &[]);
+                scrape_errors.with_label_values(&["github"]).inc();
+                scrape_failures.record_failure("github");
+            }
+            None => {}
         }
 
-        for (reviewer, count) in parse_reviewer_stats(prs.clone(), State::merged) {
-            pr_reviewer_stats_merged
-                .with_label_values(&[&reviewer])
-                .set(count.into());
+        match pr_stats {
+            Some(Some(pr_stats)) => {
+                for (reviewer, (merges, reviews)) in pr_stats {
+                    pr_merger_stats
+                        .with_label_values(&[&reviewer])
+                        .set(merges.into());
+                    pr_review_stats
+                        .with_label_values(&[&reviewer])
+                        .set(reviews.into());
+                }
+            }
+            Some(None) => {
+                // Keep the previous merge and review counts, a partial walk would undercount
+                warn!("Failed to fetch the GitHub GraphQL PR stats");
+                scrape_errors.with_label_values(&["github"]).inc();
+                scrape_failures.record_failure("github");
+            }
+            None => {}
         }
 
-        waiting_review.set(awaiting_reviews(prs).into());
-        info!("New waiting review count: {:?}", waiting_review);
+        for (org, org_data, fetched) in &hcb_data {
+            match org_data {
+                Ok(_) => {
+                    // A cache hit says nothing about whether HCB is still up
+                    if *fetched {
+                        last_successful_scrape
+                            .with_label_values(&["hcb"])
+                            .set(unix_now());
+                    }
 
-        transfers_count.set(count_transfers(&hcb_data).into());
-        info!("New transfer count: {:?}", transfers_count);
+                    transfers_count
+                        .with_label_values(&[org, "all"])
+                        .set(count_transfers(org_data));
+                    info!(
+                        "New transfer count for {}: {:?}",
+                        org,
+                        transfers_count.with_label_values(&[org, "all"])
+                    );
 
-        average_grant_value.set(avg_grant(&hcb_data));
-        info!("New average grant value: {:?}", average_grant_value);
+                    metrics_state.set_fresh(
+                        &average_grant_value,
+                        &avg_grant_stale,
+                        &[org],
+                        avg_grant(org_data),
+                    );
+                    info!(
+                        "New average grant value for {}: {:?}",
+                        org,
+                        average_grant_value.with_label_values(&[org])
+                    );
 
-        prs = fetch_pull_requests(raw_github_api_key.clone()).await;
-        hcb_data = fetch_hcb().await;
-        info!("Waiting for request...");
-    }
-}
+                    let smoothed = ema(
+                        avg_grant_ema.get(org).copied(),
+                        avg_grant(org_data),
+                        config.avg_grant_ema_alpha,
+                    );
+                    avg_grant_ema.insert(org.clone(), smoothed);
+                    avg_grant_smoothed.with_label_values(&[org]).set(smoothed);
+                }
+                Err(e) => {
+                    // Keep the previous values rather than dropping to 0
+                    warn!("Failed to fetch HCB transfers for {}: {}", org, e);
+                    for (gauge, stale) in [
+                        (&average_grant_value, &avg_grant_stale),
+                        (&median_grant_value, &median_grant_stale),
+                        (&max_grant_value, &max_grant_stale),
+                        (&total_grants_dollars, &total_grants_dollars_stale),
+                    ] {
+                        metrics_state.set_stale(gauge, stale, &[org]);
+                    }
+                    scrape_errors.with_label_values(&["hcb"]).inc();
+                    scrape_failures.record_failure("hcb");
+                }
+            }
 
-fn count_dirs() -> f64 {
-    let temp_projects_path = "projects/";
-    // Download the repo and set up the projects directory
-    git_download::repo("https://github.com/hackclub/OnBoard")
-        .branch_name("main")
-        .add_file("projects/", temp_projects_path)
-        .exec()
-        .unwrap();
+            if let Ok(transfers) = org_data {
+                let now = Utc::now();
+                for (period, days) in [("7d", 7), ("30d", 30)] {
+                    transfers_count.with_label_values(&[org, period]).set(
+                        count_transfers_since(transfers, now, chrono::Duration::days(days)).into(),
+                    );
+                }
+                // Only the last 12 months are kept, so the month that just
+                // dropped out of the window is removed rather than left behind
+                for (month, count) in count_transfers_by_month(transfers, now, 12) {
+                    transfers_count_by_month
+                        .with_label_values(&[org, &month])
+                        .set(count.into());
+                }
+                if let Some(expired) = trailing_months(now, 13).first() {
+                    let _ = transfers_count_by_month.remove_label_values(&[org, expired]);
+                }
+                grants_per_day_rate
+                    .with_label_values(&[org])
+                    .set(grants_per_day(
+                        transfers,
+                        now,
+                        config.grants_per_day_window_days,
+                    ));
+
+                if let Some((oldest, newest)) = transfer_date_range(transfers) {
+                    oldest_transfer_timestamp
+                        .with_label_values(&[org])
+                        .set(oldest.timestamp() as f64);
+                    newest_transfer_timestamp
+                        .with_label_values(&[org])
+                        .set(newest.timestamp() as f64);
+                }
 
-    // Read the entries in the projects directory
-    let entries = fs::read_dir(temp_projects_path).expect("Failed to read projects directory");
+                let stats = grant_stats(transfers);
+                metrics_state.set_fresh(
+                    &median_grant_value,
+                    &median_grant_stale,
+                    &[org],
+                    stats.median,
+                );
+                metrics_state.set_fresh(&max_grant_value, &max_grant_stale, &[org], stats.max);
+                grant_range_dollars
+                    .with_label_values(&[org])
+                    .set(stats.range);
+                metrics_state.set_fresh(
+                    &total_grants_dollars,
+                    &total_grants_dollars_stale,
+                    &[org],
+                    stats.total,
+                );
+
+                distinct_recipients_count
+                    .with_label_values(&[org])
+                    .set(distinct_recipients(transfers).into());
+
+                let (programs, unknown_program) = distinct_programs(transfers);
+                distinct_programs_count
+                    .with_label_values(&[org])
+                    .set(programs.into());
+                transfers_unknown_program
+                    .with_label_values(&[org])
+                    .set(unknown_program.into());
+
+                for (category, count) in count_transfers_by_category(transfers) {
+                    transfers_by_category
+                        .with_label_values(&[org, category])
+                        .set(count.into());
+                }
 
-    // Filter and count the directories
-    let dir_count = entries
-        .filter_map(Result::ok)
-        .filter(|e| e.path().is_dir())
-        .count() as f64; // Convert to f64, as set() expects a f64
+                for transfer in seen_transfers.observe(org, transfers) {
+                    grant_amounts.observe(transfer.amount_cents as f64 / 100.0);
+                }
+            }
+        }
 
-    // Clean up the projects directory
-    if fs::remove_dir_all(temp_projects_path).is_ok() {
-        info!("Successfully deleted everything in the /projects folder.");
-    } else {
-        info!("Failed to delete the contents of the /projects folder.");
+        // Only the running totals are kept with HCB_STREAMING, so the metrics
+        // that need every grant (median, histogram, recipients, programs and
+        // the time windows) are left out
+        for (org, org_totals) in &hcb_totals {
+            match org_totals {
+                Ok(totals) => {
+                    last_successful_scrape
+                        .with_label_values(&["hcb"])
+                        .set(unix_now());
+
+                    let stats = totals.stats();
+                    transfers_count
+                        .with_label_values(&[org, "all"])
+                        .set(stats.count as i64);
+                    metrics_state.set_fresh(
+                        &average_grant_value,
+                        &avg_grant_stale,
+                        &[org],
+                        stats.avg,
+                    );
+                    let smoothed = ema(
+                        avg_grant_ema.get(org).copied(),
+                        stats.avg,
+                        config.avg_grant_ema_alpha,
+                    );
+                    avg_grant_ema.insert(org.clone(), smoothed);
+                    avg_grant_smoothed.with_label_values(&[org]).set(smoothed);
+                    metrics_state.set_fresh(&max_grant_value, &max_grant_stale, &[org], stats.max);
+                    grant_range_dollars
+                        .with_label_values(&[org])
+                        .set(stats.range);
+                    metrics_state.set_fresh(
+                        &total_grants_dollars,
+                        &total_grants_dollars_stale,
+                        &[org],
+                        stats.total,
+                    );
+                    info!("New grant totals for {}: {:?}", org, totals);
+                }
+                Err(e) => {
+                    warn!("Failed to fetch HCB transfers for {}: {}", org, e);
+                    for (gauge, stale) in [
+                        (&average_grant_value, &avg_grant_stale),
+                        (&max_grant_value, &max_grant_stale),
+                        (&total_grants_dollars, &total_grants_dollars_stale),
+                    ] {
+                        metrics_state.set_stale(gauge, stale, &[org]);
+                    }
+                    scrape_errors.with_label_values(&["hcb"]).inc();
+                    scrape_failures.record_failure("hcb");
+                }
+            }
+        }
+
+        for source in fetched_sources {
+            circuit_breaker.record(source, !scrape_failures.has_failed(source));
+        }
+        let all_succeeded = scrape_failures.finish_cycle();
+        upstreams_reachable.set(all_succeeded.into());
+        health.last_cycle_completed.store(true, Ordering::SeqCst);
+        // Not ready until a refresh got through to every source, once ready a
+        // later failure doesn't take the exporter out of rotation
+        if all_succeeded {
+            health.ready.store(true, Ordering::SeqCst);
+        }
+
+        // Read while the values are still consistent, posted once scrapes can
+        // get at the metrics again
+        let slack_digest = match &config.slack_webhook_url {
+            Some(webhook_url)
+                if last_slack_post
+                    .is_none_or(|posted: Instant| posted.elapsed() >= config.slack_interval) =>
+            {
+                let mut digest = "*OnBoard digest*".to_string();
+                if config.enable_github {
+                    digest.push_str(&format!(
+                        "\nSubmitted projects: {}\nPRs waiting for review: {}",
+                        total_submitted_projects.get(),
+                        waiting_review.get()
+                    ));
+                }
+                for org in config.hcb.orgs.iter().filter(|_| config.enable_hcb) {
+                    digest.push_str(&format!(
+                        "\n{}: {} grants, ${:.2} total",
+                        org,
+                        transfers_count.with_label_values(&[org, "all"]).get(),
+                        total_grants_dollars.with_label_values(&[org]).get()
+                    ));
+                }
+                if config.enable_airtable {
+                    digest.push_str(&format!(
+                        "\nApproval ratio: {:.0}%",
+                        airtable_approval_ratio.get() * 100.0
+                    ));
+                    for view in &config.airtable.views {
+                        digest.push_str(&format!(
+                            "\n{} Airtable records: {}",
+                            view,
+                            airtable_records.with_label_values(&[view, ""]).get()
+                        ));
+                    }
+                }
+                Some((webhook_url, digest))
+            }
+            _ => None,
+        };
+        drop(guard);
+
+        if let Some((webhook_url, digest)) = slack_digest {
+            match post_slack_message(&client, webhook_url, &digest).await {
+                Ok(()) => info!("Posted the Slack digest"),
+                // Without the URL, it's a secret
+                Err(e) => warn!("Failed to post the Slack digest: {}", e.without_url()),
+            }
+            last_slack_post = Some(Instant::now());
+        }
+
+        if let Some((pushgateway_url, job)) = &config.pushgateway {
+            match push_metrics(&client, pushgateway_url, job, &prometheus::gather()).await {
+                Ok(()) => info!("Pushed the metrics to the Pushgateway"),
+                Err(e) => warn!("Failed to push the metrics to the Pushgateway: {:#}", e),
+            }
+        }
+
+        if config.oneshot {
+            print_metrics();
+            break;
+        }
+        next_refresh = cycle_started + jittered(config.refresh_interval, config.refresh_jitter);
+        info!(
+            "Metrics refreshed, next refresh in {:?}",
+            next_refresh.saturating_duration_since(tokio::time::Instant::now())
+        );
     }
 
-    dir_count
+    // Don't leave the cached clones, or a clone cut off halfway, behind for
+    // the next run to trip over. Only what the clones created is deleted,
+    // all of it under CHECKOUTS_DIR
+    for path in checkout_paths(&config.onboard_repos) {
+        if path.exists() && fs::remove_dir_all(&path).is_err() {
+            warn!("Failed to delete the {} folder on shutdown", path.display());
+        }
+    }
+    // Left alone if anything else ended up in there
+    let _ = fs::remove_dir(CHECKOUTS_DIR);
+    info!("Shut down cleanly");
 }
 
-async fn fetch_hcb() -> Result<Vec<Transfer>, reqwest::Error> {
-    let mut page_offset = 0;
-    let mut transfers: Vec<Transfer> = Vec::new();
+/// Makes one request to every enabled upstream, the same way a refresh would,
+/// and prints a line per check. Nothing is counted or exported. Returns whether
+/// every check passed.
+async fn validate_config(config: &Config, client: &Client) -> bool {
+    let mut results: Vec<(&str, String, anyhow::Result<()>)> = Vec::new();
+
+    if config.enable_github {
+        for repo in &config.onboard_repos {
+            let (url, branch) = (repo.url.clone(), config.onboard_repo_branch.clone());
+            let github_token = config.github_token.clone();
+            // Lists the branch without cloning anything
+            let result = tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+                let output = git_command(&url, github_token.as_deref())
+                    .args(["ls-remote", "--exit-code", "--heads", &url, &branch])
+                    .output()?;
+                if !output.status.success() {
+                    anyhow::bail!("git ls-remote exited with {}", output.status);
+                }
+                Ok(())
+            })
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|result| result);
+            let target = format!("{} ({})", repo.label, config.onboard_repo_branch);
+            results.push(("github", target, result));
+        }
+    }
 
-    loop {
-        let mut request_url: Url =
-            Url::parse("https://hcb.hackclub.com/api/v3/organizations/onboard/transfers/").unwrap();
-        request_url.query_pairs_mut().append_pair("per_page", "100");
-        request_url
-            .query_pairs_mut()
-            .append_pair("expand", "transaction");
-        request_url
-            .query_pairs_mut()
-            .append_pair("page", &page_offset.to_string());
-
-        let response = reqwest::get(request_url.as_str()).await?;
-        let json = response.json::<serde_json::Value>().await?;
-        println!(
-            r##"Fetching transfers from page {} from Onboard's Hack Club Bank API using, "{}""##,
-            page_offset + 1,
-            request_url
-        );
+    if config.enable_airtable {
+        let airtable = &config.airtable;
+        let target = format!("{}/{}", airtable.base_id, airtable.table);
+        let result = match &airtable.api_key {
+            Some(api_key) => {
+                let mut request_url: Url =
+                    Url::parse(&airtable.api_url).expect("Invalid Airtable API URL");
+                request_url
+                    .path_segments_mut()
+                    .unwrap()
+                    .pop_if_empty()
+                    .push(&airtable.base_id)
+                    .push(&airtable.table);
+                request_url.query_pairs_mut().append_pair("pageSize", "1");
+                client
+                    .get(request_url.as_str())
+                    .bearer_auth(api_key)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map(|_| ())
+                    .map_err(anyhow::Error::from)
+            }
+            None => Err(anyhow::anyhow!("no AIRTABLE_API key configured")),
+        };
+        results.push(("airtable", target, result));
+    }
 
-        if json.to_string() == "[]" {
-            break;
+    if config.enable_hcb {
+        for org in &config.hcb.orgs {
+            let request_url = format!(
+                "{}/organizations/{}/transfers/?per_page=1",
+                config.hcb.api_url.trim_end_matches('/'),
+                org
+            );
+            let result = client
+                .get(&request_url)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map(|_| ())
+                .map_err(anyhow::Error::from);
+            results.push(("hcb", org.clone(), result));
         }
+    }
 
-        if let Some(raw_transfers) = json.as_array() {
-            for raw_transfer in raw_transfers {
-                let transfer = serde_json::from_value(raw_transfer.clone()).unwrap();
-                transfers.push(transfer);
+    let mut all_ok = true;
+    for (source, target, result) in results {
+        match result {
+            Ok(()) => println!("{:<8} ok   {}", source, target),
+            Err(e) => {
+                all_ok = false;
+                println!("{:<8} FAIL {}: {:#}", source, target, e);
             }
-        } else {
-            println!("Failed to parse JSON array from response");
         }
-        page_offset += 1;
     }
+    all_ok
+}
 
-    transfers.retain(|transfer| (transfer.amount_cents / 100) <= 100);
-    Ok(transfers)
+/// Sets up the logger with default level info so we can see the refresh
+/// messages. With `json` every line is a JSON object carrying the timestamp,
+/// level, source (the log target), message and any structured fields.
+fn init_logger(json: bool) {
+    let mut builder = Builder::from_env(Env::default().default_filter_or("info"));
+    if json {
+        builder.format(|buf, record| {
+            let mut line = serde_json::Map::new();
+            line.insert("ts".to_string(), Utc::now().to_rfc3339().into());
+            line.insert("level".to_string(), record.level().as_str().into());
+            line.insert("source".to_string(), record.target().into());
+            line.insert("message".to_string(), record.args().to_string().into());
+            let mut fields = JsonFields(&mut line);
+            // Our visitor never fails, so there's no error to report
+            let _ = record.key_values().visit(&mut fields);
+            writeln!(buf, "{}", serde_json::Value::Object(line))
+        });
+    }
+    builder.init();
 }
 
-fn count_transfers(transfers: &Result<Vec<Transfer>, reqwest::Error>) -> u16 {
-    match transfers {
-        Ok(count) => return count.len() as u16,
-        Err(e) => {
-            println!("Failed to fetch transfers: {}", e);
-            return 0;
-        }
-    };
+/// Copies a log record's key-values into its JSON line, keeping numbers and
+/// booleans as such
+struct JsonFields<'a>(&'a mut serde_json::Map<String, serde_json::Value>);
+
+impl<'kvs> log::kv::VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(
+        &mut self,
+        key: log::kv::Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        let value = if let Some(value) = value.to_bool() {
+            value.into()
+        } else if let Some(value) = value.to_i64() {
+            value.into()
+        } else if let Some(value) = value.to_f64() {
+            value.into()
+        } else {
+            value.to_string().into()
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+/// Writes every registered metric to stdout in the Prometheus text format
+fn print_metrics() {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .expect("Cannot encode metrics");
+    print!("{}", String::from_utf8_lossy(&buffer));
 }
 
-fn avg_grant(transfers: &Result<Vec<Transfer>, reqwest::Error>) -> f64 {
-    match transfers {
-        Ok(transfers) => {
-            let mut total = 0;
-            for transfer in transfers {
-                total += transfer.amount_cents / 100;
+/// Serves `/metrics`, gzipped when the scraper sends `Accept-Encoding: gzip`
+/// and plain text otherwise. Encoding waits for `update_lock` so a scrape
+/// never lands in the middle of an update.
+fn spawn_metrics_server(addr: SocketAddr, update_lock: Arc<Mutex<()>>) -> anyhow::Result<()> {
+    let server = tiny_http::Server::http(addr)
+        .map_err(|e| anyhow::anyhow!("Cannot listen on {}: {}", addr, e))?;
+    info!("Exporting metrics on http://{}/metrics", addr);
+
+    thread::spawn(move || {
+        let encoder = TextEncoder::new();
+        let content_type =
+            tiny_http::Header::from_bytes("Content-Type", encoder.format_type()).unwrap();
+
+        for request in server.incoming_requests() {
+            if request.url() != "/metrics" {
+                let response =
+                    tiny_http::Response::from_string("not found\n").with_status_code(404);
+                if let Err(e) = request.respond(response) {
+                    warn!("Failed to respond to metrics request: {}", e);
+                }
+                continue;
+            }
+
+            let mut body = Vec::new();
+            {
+                let _guard = update_lock.lock().unwrap_or_else(|e| e.into_inner());
+                if let Err(e) = encoder.encode(&prometheus::gather(), &mut body) {
+                    warn!("Cannot encode metrics: {}", e);
+                }
+            }
+
+            let gzip = request
+                .headers()
+                .iter()
+                .filter(|header| header.field.equiv("Accept-Encoding"))
+                .any(|header| accepts_gzip(header.value.as_str()));
+            let mut content_encoding = None;
+            if gzip {
+                let plain_len = body.len();
+                let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+                match gz.write_all(&body).and_then(|_| gz.finish()) {
+                    Ok(compressed) => {
+                        debug!(
+                            "Gzipped /metrics from {} to {} bytes",
+                            plain_len,
+                            compressed.len()
+                        );
+                        body = compressed;
+                        content_encoding = Some("gzip");
+                    }
+                    Err(e) => warn!("Cannot gzip metrics, sending them uncompressed: {}", e),
+                }
+            }
+
+            let mut response = tiny_http::Response::from_data(body)
+                .with_header(content_type.clone())
+                .with_header(tiny_http::Header::from_bytes("Vary", "Accept-Encoding").unwrap());
+            if let Some(encoding) = content_encoding {
+                response = response.with_header(
+                    tiny_http::Header::from_bytes("Content-Encoding", encoding).unwrap(),
+                );
+            }
+            if let Err(e) = request.respond(response) {
+                warn!("Failed to respond to metrics request: {}", e);
             }
-            return total as f64 / transfers.len() as f64;
         }
+    });
+    Ok(())
+}
+
+/// Whether an `Accept-Encoding` value allows gzip, i.e. lists it (or `*`)
+/// without `q=0`
+fn accepts_gzip(accept_encoding: &str) -> bool {
+    accept_encoding.split(',').any(|coding| {
+        let mut parts = coding.split(';').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        let refused = parts.any(|param| {
+            param
+                .strip_prefix("q=")
+                .and_then(|q| q.parse::<f64>().ok())
+                .is_some_and(|q| q == 0.0)
+        });
+        (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+    })
+}
+
+/// Serves `/healthz` (liveness) and `/readyz` (readiness) on their own port so
+/// probes never trigger the upstream fetches behind `/metrics`.
+fn spawn_health_server(addr: SocketAddr, health: Arc<HealthState>) {
+    let server = match tiny_http::Server::http(addr) {
+        Ok(server) => server,
         Err(e) => {
-            println!("Failed to fetch transfers: {}", e);
-            return 0.0;
+            warn!("Cannot start health server on {}: {}", addr, e);
+            return;
         }
     };
+    info!("Health endpoints listening on http://{}/healthz", addr);
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let ready = health.ready.load(Ordering::SeqCst);
+            let last_cycle_completed = health.last_cycle_completed.load(Ordering::SeqCst);
+
+            let (status, body): (u16, String) = match request.url() {
+                "/healthz" => (
+                    200,
+                    format!("ok\nlast_cycle_completed: {}\n", last_cycle_completed),
+                ),
+                "/readyz" if ready => (200, "ready\n".to_string()),
+                "/readyz" => (503, "waiting for the first metrics update\n".to_string()),
+                _ => (404, "not found\n".to_string()),
+            };
+
+            let response = tiny_http::Response::from_string(body).with_status_code(status);
+            if let Err(e) = request.respond(response) {
+                warn!("Failed to respond to health check: {}", e);
+            }
+        }
+    });
 }
 
-async fn airtable_verifications(
-    api_key: Result<String, env::VarError>,
-    AirTableView: AirTableViews,
-) -> u16 {
-    let max_records = 5000;
-    let mut page_offset: Option<String> = None;
-    let view;
-    match AirTableView {
-        AirTableViews::Pending => view = "Pending",
-        AirTableViews::Approved => view = "Approved",
+fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+/// The HTTP client every upstream is fetched with
+fn build_client(config: &Config) -> Client {
+    let mut client = with_env_proxies(Client::builder());
+    if let Some((_, identity)) = &config.tls_client_cert {
+        client = client.identity(identity.clone());
     }
-    let mut num_records = 0;
-    let true_api_key;
-    let mut page_offset_count = 0;
+    client
+        .user_agent(USER_AGENT)
+        // We only ever talk to a handful of hosts, one after the other
+        .pool_max_idle_per_host(4)
+        .connect_timeout(config.http_timeout)
+        .timeout(config.http_timeout)
+        .build()
+        .expect("Cannot build HTTP client")
+}
 
-    match api_key {
-        Ok(key) => {
-            info!("Airtable API key found");
-            true_api_key = key;
+/// Routes requests through the proxies named by the standard `HTTPS_PROXY`,
+/// `HTTP_PROXY` and `ALL_PROXY` env vars (either case), skipping any host
+/// matched by `NO_PROXY`.
+fn with_env_proxies(mut builder: ClientBuilder) -> ClientBuilder {
+    let env_any = |keys: &[&str]| keys.iter().find_map(|key| env::var(key).ok());
+    let mut detected = Vec::new();
+
+    if let Some(url) = env_any(&["HTTPS_PROXY", "https_proxy"]) {
+        match Proxy::https(url.as_str()) {
+            Ok(proxy) => {
+                builder = builder.proxy(proxy.no_proxy(NoProxy::from_env()));
+                detected.push("HTTPS_PROXY");
+            }
+            Err(e) => warn!("Ignoring invalid HTTPS_PROXY: {}", e),
+        }
+    }
+    if let Some(url) = env_any(&["HTTP_PROXY", "http_proxy"]) {
+        match Proxy::http(url.as_str()) {
+            Ok(proxy) => {
+                builder = builder.proxy(proxy.no_proxy(NoProxy::from_env()));
+                detected.push("HTTP_PROXY");
+            }
+            Err(e) => warn!("Ignoring invalid HTTP_PROXY: {}", e),
         }
-        Err(_) => {
-            info!("Airtable API key not found");
-            return 0;
+    }
+    if let Some(url) = env_any(&["ALL_PROXY", "all_proxy"]) {
+        match Proxy::all(url.as_str()) {
+            Ok(proxy) => {
+                builder = builder.proxy(proxy.no_proxy(NoProxy::from_env()));
+                detected.push("ALL_PROXY");
+            }
+            Err(e) => warn!("Ignoring invalid ALL_PROXY: {}", e),
         }
     }
-    loop {
-        let mut request_url: Url =
-            Url::parse("https://api.airtable.com/v0/app4Bs8Tjwvk5qcD4/Submissions").unwrap();
-        request_url
-            .query_pairs_mut()
-            .append_pair("maxRecords", &max_records.to_string());
-        request_url.query_pairs_mut().append_pair("view", &view);
-
-        match &page_offset {
-            Some(offset) => {
-                request_url
-                    .query_pairs_mut()
-                    .append_pair("offset", offset.as_str());
+
+    // Proxy URLs can carry credentials, so only say which vars were picked up
+    if detected.is_empty() {
+        info!("No HTTP proxy detected, connecting directly");
+    } else {
+        info!(
+            "Using HTTP proxy from {} (NO_PROXY {})",
+            detected.join(", "),
+            if NoProxy::from_env().is_some() {
+                "respected"
+            } else {
+                "not set"
             }
-            None => {}
+        );
+    }
+    builder
+}
+
+/// Command line flags, each falling back to the environment variable of the
+/// same name and then to a default. Kept as raw strings so `Config` can
+/// validate them the same way whichever way they were passed.
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Prometheus exporter for OnBoard projects, HCB grants and Airtable submissions",
+    long_about = None
+)]
+struct Args {
+    /// Address to serve /metrics on [default: 0.0.0.0:8521]
+    #[arg(long, env = "EXPORTER_ADDR")]
+    exporter_addr: Option<String>,
+    /// Address to serve /healthz and /readyz on [default: exporter port + 1]
+    #[arg(long, env = "HEALTH_ADDR")]
+    health_addr: Option<String>,
+    /// Seconds between refreshes of the upstream data [default: 60]
+    #[arg(long, env = "REFRESH_INTERVAL_SECS")]
+    refresh_interval_secs: Option<String>,
+    /// Fraction the refresh interval randomly varies by, in [0, 1) [default: 0.1]
+    #[arg(long, env = "REFRESH_JITTER")]
+    refresh_jitter: Option<String>,
+    /// Seconds before an upstream request times out [default: 10]
+    #[arg(long, env = "HTTP_TIMEOUT_SECS")]
+    http_timeout_secs: Option<String>,
+    /// Refreshes in a row a source has to fail before its circuit opens [default: 3]
+    #[arg(long, env = "CIRCUIT_FAILURE_THRESHOLD")]
+    circuit_failure_threshold: Option<String>,
+    /// Seconds a source with an open circuit is skipped for, 0 to never skip [default: 0]
+    #[arg(long, env = "CIRCUIT_COOLDOWN_SECS")]
+    circuit_cooldown_secs: Option<String>,
+    /// Retries of a failed upstream request [default: 3]
+    #[arg(long, env = "HTTP_MAX_RETRIES")]
+    http_max_retries: Option<String>,
+    /// PEM client certificate presented to the upstreams, for mutual TLS proxies
+    #[arg(
+        long,
+        env = "TLS_CLIENT_CERT",
+        value_name = "PATH",
+        requires = "tls_client_key"
+    )]
+    tls_client_cert: Option<PathBuf>,
+    /// PEM (PKCS#8) private key of --tls-client-cert
+    #[arg(
+        long,
+        env = "TLS_CLIENT_KEY",
+        value_name = "PATH",
+        requires = "tls_client_cert"
+    )]
+    tls_client_key: Option<PathBuf>,
+    /// Fetch and export HCB grants [default: true]
+    #[arg(long, env = "ENABLE_HCB", value_name = "BOOL")]
+    enable_hcb: Option<String>,
+    /// Fetch and export Airtable record counts [default: true]
+    #[arg(long, env = "ENABLE_AIRTABLE", value_name = "BOOL")]
+    enable_airtable: Option<String>,
+    /// Fetch and export GitHub project and PR stats [default: true]
+    #[arg(long, env = "ENABLE_GITHUB", value_name = "BOOL")]
+    enable_github: Option<String>,
+    /// HCB API root [default: https://hcb.hackclub.com/api/v3]
+    #[arg(long, env = "HCB_API_URL")]
+    hcb_api_url: Option<String>,
+    /// Comma-separated HCB organization slugs [default: onboard]
+    #[arg(long, env = "HCB_ORGS")]
+    hcb_orgs: Option<String>,
+    /// Transfers fetched per HCB page, at most 100 [default: 100]
+    #[arg(long, env = "HCB_PAGE_SIZE")]
+    hcb_page_size: Option<String>,
+    /// Comma-separated transaction types counted as grants [default: all]
+    #[arg(long, env = "HCB_INCLUDE_TYPES")]
+    hcb_include_types: Option<String>,
+    /// Comma-separated transaction types never counted as grants
+    #[arg(long, env = "HCB_EXCLUDE_TYPES")]
+    hcb_exclude_types: Option<String>,
+    /// Keep only running totals per HCB org instead of every transfer, which
+    /// drops the median, histogram and other per-grant metrics [default: false]
+    #[arg(long, env = "HCB_STREAMING", value_name = "BOOL")]
+    hcb_streaming: Option<String>,
+    /// Seconds fetched HCB transfers are reused for [default: 300]
+    #[arg(long, env = "HCB_CACHE_TTL_SECS")]
+    hcb_cache_ttl_secs: Option<String>,
+    /// Transfers above this many dollars aren't counted as grants [default: 100]
+    #[arg(long, env = "MAX_GRANT_DOLLARS")]
+    max_grant_dollars: Option<String>,
+    /// Weight of the newest average in avg_grant_smoothed, in (0, 1] [default: 0.3]
+    #[arg(long, env = "AVG_GRANT_EMA_ALPHA")]
+    avg_grant_ema_alpha: Option<String>,
+    /// Trailing days grants_per_day averages over [default: 30]
+    #[arg(long, env = "GRANTS_PER_DAY_WINDOW_DAYS")]
+    grants_per_day_window_days: Option<String>,
+    /// Comma-separated upper bounds in dollars of grant_amount_dollars [default: 5,10,25,50,75,100]
+    #[arg(long, env = "GRANT_HISTOGRAM_BUCKETS")]
+    grant_histogram_buckets: Option<String>,
+    /// Airtable API key, nothing is counted without one
+    #[arg(long, env = "AIRTABLE_API", hide_env_values = true)]
+    airtable_api: Option<String>,
+    /// File holding the Airtable API key, preferred over --airtable-api
+    #[arg(long, env = "AIRTABLE_API_FILE", value_name = "PATH")]
+    airtable_api_file: Option<PathBuf>,
+    /// GitHub API root [default: https://api.github.com]
+    #[arg(long, env = "GITHUB_API_URL")]
+    github_api_url: Option<String>,
+    /// Airtable API root [default: https://api.airtable.com/v0]
+    #[arg(long, env = "AIRTABLE_API_URL")]
+    airtable_api_url: Option<String>,
+    /// Airtable base holding the submissions [default: app4Bs8Tjwvk5qcD4]
+    #[arg(long, env = "AIRTABLE_BASE_ID")]
+    airtable_base_id: Option<String>,
+    /// Airtable table holding the submissions [default: Submissions]
+    #[arg(long, env = "AIRTABLE_TABLE")]
+    airtable_table: Option<String>,
+    /// Comma-separated Airtable views to count [default: Approved,Pending]
+    #[arg(long, env = "AIRTABLE_VIEWS")]
+    airtable_views: Option<String>,
+    /// Airtable formula to also count matching records of, e.g. {Status}='Flagged'
+    #[arg(long, env = "AIRTABLE_FILTER_FORMULA")]
+    airtable_filter_formula: Option<String>,
+    /// Airtable field with the submission time [default: the record's creation time]
+    #[arg(long, env = "AIRTABLE_SUBMITTED_FIELD")]
+    airtable_submitted_field: Option<String>,
+    /// Airtable field with the approval time, enables airtable_approval_seconds
+    #[arg(long, env = "AIRTABLE_APPROVED_FIELD")]
+    airtable_approved_field: Option<String>,
+    /// GitHub API key for the pull request stats
+    #[arg(long, env = "GITHUB_API", hide_env_values = true)]
+    github_api: Option<String>,
+    /// File holding the GitHub API key, preferred over --github-api
+    #[arg(long, env = "GITHUB_API_FILE", value_name = "PATH")]
+    github_api_file: Option<PathBuf>,
+    /// GitHub token for the clone and GraphQL stats, the GraphQL stats fall
+    /// back to --github-api
+    #[arg(long, env = "GITHUB_TOKEN", hide_env_values = true)]
+    github_token: Option<String>,
+    /// File holding the GitHub token, preferred over --github-token
+    #[arg(long, env = "GITHUB_TOKEN_FILE", value_name = "PATH")]
+    github_token_file: Option<PathBuf>,
+    /// Only count OnBoard PRs carrying this label in onboard_prs [default: all PRs]
+    #[arg(long, env = "GITHUB_PR_LABEL")]
+    github_pr_label: Option<String>,
+    /// Git URL of the OnBoard repo, or a fork of it [default: https://github.com/hackclub/OnBoard]
+    #[arg(long, env = "ONBOARD_REPO_URL")]
+    onboard_repo_url: Option<String>,
+    /// Comma-separated repos to count projects across, overrides --onboard-repo-url
+    #[arg(long, env = "ONBOARD_REPOS")]
+    onboard_repos: Option<String>,
+    /// Branch of the OnBoard repo to count projects on [default: main]
+    #[arg(long, env = "ONBOARD_REPO_BRANCH")]
+    onboard_repo_branch: Option<String>,
+    /// Subtree of the OnBoard repo holding the projects [default: projects/]
+    #[arg(long, env = "PROJECTS_PATH")]
+    projects_path: Option<String>,
+    /// Whether projects are grouped as <projects>/<category>/<project> [default: false]
+    #[arg(long, env = "PROJECTS_CATEGORIZED", value_name = "BOOL")]
+    projects_categorized: Option<String>,
+    /// Seconds a clone of the OnBoard repo is reused for [default: 300]
+    #[arg(long, env = "REPO_CACHE_TTL_SECS")]
+    repo_cache_ttl_secs: Option<String>,
+    /// Slack incoming webhook to post a digest of the key metrics to
+    #[arg(long, env = "SLACK_WEBHOOK_URL", hide_env_values = true)]
+    slack_webhook_url: Option<String>,
+    /// Minimum seconds between Slack digests [default: every refresh]
+    #[arg(long, env = "SLACK_INTERVAL_SECS")]
+    slack_interval_secs: Option<String>,
+    /// Constant labels added to every metric, e.g. env=prod,region=us
+    #[arg(long, env = "STATIC_LABELS")]
+    static_labels: Option<String>,
+    /// Pushgateway to push the metrics to after each refresh, on top of serving them
+    #[arg(long, env = "PUSHGATEWAY_URL")]
+    pushgateway_url: Option<String>,
+    /// Job name the metrics are pushed under [default: onboard_grant_aggregator]
+    #[arg(long, env = "PUSHGATEWAY_JOB")]
+    pushgateway_job: Option<String>,
+    /// Run a single refresh, print the metrics to stdout and exit
+    #[arg(long, env = "ONESHOT", value_parser = FalseyValueParser::new())]
+    once: bool,
+    /// Log as JSON lines instead of plain text
+    #[arg(long, env = "LOG_FORMAT", value_parser = ["text", "json"], default_value = "text")]
+    log_format: String,
+    /// Log upstream payloads that fail to parse at debug level, they can
+    /// contain personal details [default: false]
+    #[arg(long, env = "DEBUG_DUMP_JSON", value_name = "BOOL")]
+    debug_dump_json: Option<String>,
+    /// Try one request against each enabled upstream, report the results and exit
+    #[arg(long, env = "VALIDATE_CONFIG", value_parser = FalseyValueParser::new())]
+    validate_config: bool,
+}
+
+/// Everything read from the flags and environment, parsed and validated once at startup
+struct Config {
+    addr: SocketAddr,
+    health_addr: SocketAddr,
+    airtable: AirtableConfig,
+    hcb: HcbConfig,
+    /// REST and GraphQL root the PR stats are fetched from
+    github_api_url: String,
+    github_api_key: Option<String>,
+    /// Only `GITHUB_TOKEN`, the clone never falls back to the API key since
+    /// images ship a placeholder `GITHUB_API`
+    github_token: Option<String>,
+    /// Label `onboard_prs` is limited to, if any
+    github_pr_label: Option<String>,
+    /// How long a cloned copy of the OnBoard repo is reused before re-cloning
+    repo_cache_ttl: Duration,
+    /// Repos the projects are cloned from, the first one is also walked for
+    /// the category, README and manifest breakdowns
+    onboard_repos: Vec<OnboardRepo>,
+    onboard_repo_branch: String,
+    /// Subtree of the OnBoard repo holding the projects
+    projects_path: String,
+    /// Whether the projects are split into category folders one level down
+    projects_categorized: bool,
+    // Sources that are switched off are neither fetched nor exported
+    enable_hcb: bool,
+    enable_airtable: bool,
+    enable_github: bool,
+    /// Upstreams are fetched on this cadence, independent of how often we're scraped
+    refresh_interval: Duration,
+    /// Fraction the refresh interval is randomly stretched or shrunk by each cycle
+    refresh_jitter: f64,
+    http_timeout: Duration,
+    /// Client certificate path and the identity loaded from it, if any
+    tls_client_cert: Option<(PathBuf, Identity)>,
+    /// Weight of the newest average in avg_grant_smoothed
+    avg_grant_ema_alpha: f64,
+    /// Trailing window of grants_per_day, at least a day
+    grants_per_day_window_days: u32,
+    /// Upper bounds of grant_amount_dollars, positive and increasing
+    grant_histogram_buckets: Vec<f64>,
+    /// Where to post a digest of the key metrics, if anywhere
+    slack_webhook_url: Option<String>,
+    /// Minimum time between digests, every refresh by default
+    slack_interval: Duration,
+    /// Constant labels every series carries
+    static_labels: HashMap<String, String>,
+    /// Failed refreshes in a row before a source is skipped for `circuit_cooldown`
+    circuit_failure_threshold: u32,
+    circuit_cooldown: Duration,
+    /// Pushgateway and job name to push to after each refresh, if any
+    pushgateway: Option<(String, String)>,
+    /// Run a single refresh, print the metrics and exit without binding any sockets
+    oneshot: bool,
+    /// Only check that every enabled upstream is reachable, then exit
+    validate_only: bool,
+}
+
+impl Config {
+    /// Unparsable tunables fall back to their defaults with a warning, while
+    /// values the fetches can't work with at all are an error
+    fn from_args(args: Args) -> anyhow::Result<Config> {
+        let addr = parse_listen_addr(args.exporter_addr);
+        let health_addr = match args.health_addr {
+            Some(health_addr) => health_addr
+                .parse()
+                .with_context(|| format!("HEALTH_ADDR \"{}\" is not an address", health_addr))?,
+            None => match addr.port().checked_add(1) {
+                Some(port) => SocketAddr::new(addr.ip(), port),
+                None => anyhow::bail!(
+                    "EXPORTER_ADDR uses port {}, set HEALTH_ADDR explicitly",
+                    addr.port()
+                ),
+            },
+        };
+
+        let max_retries = args
+            .http_max_retries
+            .and_then(|retries| retries.parse().ok())
+            .unwrap_or(3);
+
+        let dump_json = parse_flag("DEBUG_DUMP_JSON", args.debug_dump_json, false);
+
+        let airtable = AirtableConfig {
+            api_url: parse_url("AIRTABLE_API_URL", args.airtable_api_url, AIRTABLE_API_URL)?,
+            api_key: parse_bearer_token(
+                "AIRTABLE_API",
+                read_secret(
+                    "AIRTABLE_API_FILE",
+                    args.airtable_api_file,
+                    args.airtable_api,
+                )?,
+            )?,
+            base_id: or_default(
+                "AIRTABLE_BASE_ID",
+                args.airtable_base_id,
+                "app4Bs8Tjwvk5qcD4",
+            ),
+            table: or_default("AIRTABLE_TABLE", args.airtable_table, "Submissions"),
+            views: or_default("AIRTABLE_VIEWS", args.airtable_views, "Approved,Pending")
+                .split(',')
+                .map(|view| view.trim().to_string())
+                .filter(|view| !view.is_empty())
+                .collect(),
+            // Optional extra count of the records matching an arbitrary Airtable formula
+            filter_formula: args
+                .airtable_filter_formula
+                .filter(|formula| !formula.is_empty()),
+            submitted_field: args.airtable_submitted_field,
+            approved_field: args.airtable_approved_field,
+            max_retries,
+            dump_json,
+        };
+
+        let hcb_page_size = args
+            .hcb_page_size
+            .and_then(|size| size.parse::<u32>().ok())
+            .unwrap_or(HCB_MAX_PAGE_SIZE);
+        if !(1..=HCB_MAX_PAGE_SIZE).contains(&hcb_page_size) {
+            warn!(
+                target: "hcb",
+                "HCB_PAGE_SIZE {} is outside 1..={}, clamping",
+                hcb_page_size,
+                HCB_MAX_PAGE_SIZE
+            );
+        }
+        let hcb = HcbConfig {
+            api_url: parse_url("HCB_API_URL", args.hcb_api_url, HCB_API_URL)?,
+            orgs: args
+                .hcb_orgs
+                .unwrap_or_else(|| "onboard".to_string())
+                .split(',')
+                .map(|org| org.trim().to_string())
+                .filter(|org| !org.is_empty())
+                .collect(),
+            max_grant_dollars: parse_max_grant_dollars(args.max_grant_dollars),
+            page_size: hcb_page_size.clamp(1, HCB_MAX_PAGE_SIZE),
+            include_types: split_list(args.hcb_include_types),
+            exclude_types: split_list(args.hcb_exclude_types),
+            cache_ttl: parse_secs(args.hcb_cache_ttl_secs, 300),
+            max_retries,
+            streaming: parse_flag("HCB_STREAMING", args.hcb_streaming, false),
+            dump_json,
+        };
+        let enable_hcb = parse_flag("ENABLE_HCB", args.enable_hcb, true);
+        if enable_hcb && hcb.orgs.is_empty() {
+            anyhow::bail!("HCB_ORGS lists no organizations, set ENABLE_HCB=false instead");
         }
 
-        let auth_token: String = format!("Bearer {}", true_api_key);
+        let slack_webhook_url = match args.slack_webhook_url {
+            Some(url) => {
+                Url::parse(&url).context("SLACK_WEBHOOK_URL is not a valid URL")?;
+                Some(url)
+            }
+            None => None,
+        };
+
+        let pushgateway = match args.pushgateway_url.filter(|url| !url.is_empty()) {
+            Some(url) => {
+                Url::parse(&url).context("PUSHGATEWAY_URL is not a valid URL")?;
+                let job = or_default(
+                    "PUSHGATEWAY_JOB",
+                    args.pushgateway_job,
+                    "onboard_grant_aggregator",
+                );
+                Some((url, job))
+            }
+            None => None,
+        };
 
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            AUTHORIZATION,
-            HeaderValue::from_str(&auth_token).expect("Invalid header value"),
+        let tls_client_cert = match (args.tls_client_cert, args.tls_client_key) {
+            (Some(cert), Some(key)) => {
+                let identity = load_identity(&cert, &key)?;
+                Some((cert, identity))
+            }
+            _ => None,
+        };
+
+        let projects_path = parse_projects_path(args.projects_path)?;
+        let repo_urls = match split_list(args.onboard_repos) {
+            urls if urls.is_empty() => vec![parse_url(
+                "ONBOARD_REPO_URL",
+                args.onboard_repo_url,
+                "https://github.com/hackclub/OnBoard",
+            )?],
+            urls => urls,
+        };
+        let mut onboard_repos = Vec::new();
+        for url in repo_urls {
+            let repo = OnboardRepo::new(url)?;
+            if let Some(other) = onboard_repos
+                .iter()
+                .find(|other: &&OnboardRepo| other.dest == repo.dest)
+            {
+                if other.label == repo.label {
+                    anyhow::bail!("ONBOARD_REPOS lists {} twice", repo.label);
+                }
+                anyhow::bail!(
+                    "ONBOARD_REPOS {} and {} would share a checkout",
+                    other.label,
+                    repo.label
+                );
+            }
+            onboard_repos.push(repo);
+        }
+
+        let github_api_key = parse_bearer_token(
+            "GITHUB_API",
+            read_secret("GITHUB_API_FILE", args.github_api_file, args.github_api)?,
+        )?;
+        let github_token = parse_bearer_token(
+            "GITHUB_TOKEN",
+            read_secret(
+                "GITHUB_TOKEN_FILE",
+                args.github_token_file,
+                args.github_token,
+            )?,
+        )?;
+
+        Ok(Config {
+            addr,
+            health_addr,
+            airtable,
+            hcb,
+            github_api_url: parse_url("GITHUB_API_URL", args.github_api_url, GITHUB_API_URL)?,
+            github_api_key,
+            github_token,
+            github_pr_label: args.github_pr_label.filter(|label| !label.is_empty()),
+            repo_cache_ttl: parse_secs(args.repo_cache_ttl_secs, 300),
+            onboard_repos,
+            onboard_repo_branch: or_default(
+                "ONBOARD_REPO_BRANCH",
+                args.onboard_repo_branch,
+                "main",
+            ),
+            projects_path,
+            projects_categorized: parse_flag(
+                "PROJECTS_CATEGORIZED",
+                args.projects_categorized,
+                false,
+            ),
+            enable_hcb,
+            enable_airtable: parse_flag("ENABLE_AIRTABLE", args.enable_airtable, true),
+            enable_github: parse_flag("ENABLE_GITHUB", args.enable_github, true),
+            refresh_interval: parse_refresh_interval(args.refresh_interval_secs),
+            refresh_jitter: parse_refresh_jitter(args.refresh_jitter),
+            http_timeout: parse_secs(args.http_timeout_secs, 10),
+            tls_client_cert,
+            avg_grant_ema_alpha: args
+                .avg_grant_ema_alpha
+                .and_then(|alpha| alpha.parse::<f64>().ok())
+                .filter(|alpha| *alpha > 0.0 && *alpha <= 1.0)
+                .unwrap_or(0.3),
+            grants_per_day_window_days: args
+                .grants_per_day_window_days
+                .and_then(|days| days.parse::<u32>().ok())
+                .filter(|days| *days > 0)
+                .unwrap_or(30),
+            grant_histogram_buckets: parse_grant_buckets(args.grant_histogram_buckets),
+            slack_webhook_url,
+            slack_interval: parse_secs(args.slack_interval_secs, 0),
+            static_labels: parse_static_labels(args.static_labels),
+            circuit_failure_threshold: args
+                .circuit_failure_threshold
+                .and_then(|threshold| threshold.parse().ok())
+                .unwrap_or(3),
+            circuit_cooldown: parse_secs(args.circuit_cooldown_secs, 0),
+            pushgateway,
+            oneshot: args.once,
+            validate_only: args.validate_config,
+        })
+    }
+
+    /// Logs the effective configuration, leaving out the API keys
+    fn log(&self) {
+        info!(
+            "Exporting on {}, health on {}, refreshing every {:?} ±{}%",
+            self.addr,
+            self.health_addr,
+            self.refresh_interval,
+            self.refresh_jitter * 100.0
+        );
+        info!(
+            "Sources enabled: hcb={} airtable={} github={}",
+            self.enable_hcb, self.enable_airtable, self.enable_github
+        );
+        info!(
+            target: "hcb",
+            "Querying {} for orgs {:?}, {} per page, cached for {:?} (streaming: {})",
+            self.hcb.api_url,
+            self.hcb.orgs,
+            self.hcb.page_size,
+            self.hcb.cache_ttl,
+            self.hcb.streaming
+        );
+        info!(
+            target: "hcb",
+            "Counting grants up to ${} of types {:?} (all if empty) except {:?}",
+            self.hcb.max_grant_dollars,
+            self.hcb.include_types,
+            self.hcb.exclude_types
         );
+        info!(
+            target: "airtable",
+            "Querying Airtable base {} table {} views {:?}, filter {:?}, API key {}",
+            self.airtable.base_id,
+            self.airtable.table,
+            self.airtable.views,
+            self.airtable.filter_formula,
+            if self.airtable.api_key.is_some() { "set" } else { "not set" }
+        );
+        info!(
+            target: "github",
+            "Counting {} of {:?} ({}) (categorized: {}), clone cached for {:?}, token {}",
+            self.projects_path,
+            self.onboard_repos
+                .iter()
+                .map(|repo| repo.url.as_str())
+                .collect::<Vec<_>>(),
+            self.onboard_repo_branch,
+            self.projects_categorized,
+            self.repo_cache_ttl,
+            if self.github_token.is_some() { "set" } else { "not set" }
+        );
+        info!(
+            "HTTP timeout {:?}, {} retries, avg_grant_smoothed alpha {}",
+            self.http_timeout, self.hcb.max_retries, self.avg_grant_ema_alpha
+        );
+        if self.hcb.dump_json {
+            info!("DEBUG_DUMP_JSON is on, payloads that fail to parse are logged at debug level");
+        }
+        if !self.circuit_cooldown.is_zero() {
+            info!(
+                "Skipping a source for {:?} after {} failed refreshes in a row",
+                self.circuit_cooldown, self.circuit_failure_threshold
+            );
+        }
+        match &self.tls_client_cert {
+            Some((cert, _)) => info!("Presenting TLS client certificate {}", cert.display()),
+            None => info!("No TLS client certificate configured"),
+        }
+        // The webhook URL is a secret of its own, only say whether it's there
+        if self.slack_webhook_url.is_some() {
+            info!(
+                "Posting a Slack digest at most every {:?}",
+                self.slack_interval
+            );
+        }
+        if let Some((url, job)) = &self.pushgateway {
+            info!(
+                "Pushing the metrics to {} as job {} after each refresh",
+                url, job
+            );
+        }
+        if !self.static_labels.is_empty() {
+            info!(
+                "Adding static labels {:?} to every metric",
+                self.static_labels
+            );
+        }
+    }
+}
 
-        let response = Client::new()
-            .get(request_url.as_str())
-            .headers(headers)
-            .send()
-            .await;
-        let json = response.unwrap().json::<serde_json::Value>().await;
-        println!(
-            r##"Fetching transfers from OnBoard's AirTable accepted verision forms using, "{}", on page {}."##,
-            request_url,
-            page_offset_count + 1
+/// A repo projects are counted in and where its projects tree is cloned to
+#[derive(Clone)]
+struct OnboardRepo {
+    url: String,
+    /// `owner/name`, the `repo` label of submitted_projects
+    label: String,
+    dest: PathBuf,
+}
+
+impl OnboardRepo {
+    /// Each repo is checked out in a directory of its own under `CHECKOUTS_DIR`
+    fn new(url: String) -> anyhow::Result<OnboardRepo> {
+        let parsed = Url::parse(&url)
+            .with_context(|| format!("ONBOARD_REPOS \"{}\" is not a valid URL", url))?;
+        let label = parsed
+            .path()
+            .trim_matches('/')
+            .trim_end_matches(".git")
+            .to_string();
+        let label = if label.is_empty() { url.clone() } else { label };
+
+        let slug: String = label
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let dest = Path::new(CHECKOUTS_DIR).join(slug);
+
+        Ok(OnboardRepo { url, label, dest })
+    }
+}
+
+/// Stretches or shrinks `interval` by a random fraction of up to `jitter`, so
+/// replicas started together drift apart instead of refreshing in lockstep
+fn jittered(interval: Duration, jitter: f64) -> Duration {
+    if jitter <= 0.0 {
+        return interval;
+    }
+    interval.mul_f64(1.0 + rand::thread_rng().gen_range(-jitter..=jitter))
+}
+
+/// Reads the subtree of the repo holding the projects, normalized to e.g.
+/// `src/projects`. It has to name a directory inside the repo, so empty, `.`,
+/// `..` and absolute paths are refused.
+fn parse_projects_path(raw: Option<String>) -> anyhow::Result<String> {
+    let raw = raw.unwrap_or_else(|| "projects/".to_string());
+    let mut parts = Vec::new();
+    for component in Path::new(raw.trim()).components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy().into_owned()),
+            Component::CurDir => {}
+            _ => anyhow::bail!(
+                "PROJECTS_PATH \"{}\" must be a directory inside the repo, e.g. projects/",
+                raw
+            ),
+        }
+    }
+    if parts.is_empty() {
+        anyhow::bail!(
+            "PROJECTS_PATH \"{}\" must be a directory inside the repo, e.g. projects/",
+            raw
         );
+    }
+    Ok(parts.join("/"))
+}
 
-        let raw_data = json.unwrap().clone();
+/// Parses a whole number of seconds, falling back to `default` when unset or unparsable
+fn parse_secs(raw: Option<String>, default: u64) -> Duration {
+    Duration::from_secs(raw.and_then(|secs| secs.parse().ok()).unwrap_or(default))
+}
 
-        if let Some(records) = raw_data.get("records") {
-            if let Some(records_array) = records.as_array() {
-                num_records += records_array.len();
+/// Reads a base URL with `or_default`, refusing anything that doesn't parse
+fn parse_url(key: &str, raw: Option<String>, default: &str) -> anyhow::Result<String> {
+    let url = or_default(key, raw, default);
+    Url::parse(&url).with_context(|| format!("{} \"{}\" is not a valid URL", key, url))?;
+    Ok(url)
+}
 
-                if raw_data.get("offset").is_some() {
-                    page_offset = Some(
-                        raw_data
-                            .get("offset")
-                            .unwrap()
-                            .to_string()
-                            .replace("\"", ""),
-                    );
-                    page_offset_count += 1;
-                } else if page_offset_count > 0 {
-                    println!(
-                        "[{}]Multiple pages of AirTable data fetched",
-                        page_offset_count + 1
-                    );
-                    return num_records as u16;
-                } else {
-                    return num_records as u16;
-                }
+/// Reads a secret from `file` (the Docker / Kubernetes secrets convention)
+/// when set, falling back to the plain value. Trailing whitespace and newlines
+/// are trimmed off the file's contents.
+fn read_secret(
+    key: &str,
+    file: Option<PathBuf>,
+    value: Option<String>,
+) -> anyhow::Result<Option<String>> {
+    match file {
+        Some(file) => {
+            let secret = fs::read_to_string(&file)
+                .with_context(|| format!("Cannot read {} {}", key, file.display()))?;
+            Ok(Some(secret.trim_end().to_string()))
+        }
+        None => Ok(value),
+    }
+}
 
-                raw_data.get("error").map(|error| {
-                    println!("Error: {}", error);
-                });
-            } else {
-                println!("The AirTable JSON is Invalid");
+/// Checks an optional API key, refusing one that can't be sent as a bearer
+/// token instead of panicking on it mid-refresh
+fn parse_bearer_token(key: &str, raw: Option<String>) -> anyhow::Result<Option<String>> {
+    match raw {
+        Some(token) => {
+            HeaderValue::from_str(&format!("Bearer {}", token))
+                .with_context(|| format!("{} contains characters not allowed in a header", key))?;
+            Ok(Some(token))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Reads a PEM certificate (chain) and its PKCS#8 key for mutual TLS
+fn load_identity(cert: &Path, key: &Path) -> anyhow::Result<Identity> {
+    let cert_pem = fs::read(cert)
+        .with_context(|| format!("Cannot read TLS_CLIENT_CERT {}", cert.display()))?;
+    let key_pem =
+        fs::read(key).with_context(|| format!("Cannot read TLS_CLIENT_KEY {}", key.display()))?;
+    Identity::from_pkcs8_pem(&cert_pem, &key_pem)
+        .context("TLS_CLIENT_CERT and TLS_CLIENT_KEY are not a valid PEM certificate and key")
+}
+
+/// Parses "true"/"1" and "false"/"0", falling back to `default`
+fn parse_flag(key: &str, raw: Option<String>, default: bool) -> bool {
+    match raw.map(|value| value.trim().to_lowercase()) {
+        Some(value) if value == "true" || value == "1" => true,
+        Some(value) if value == "false" || value == "0" => false,
+        Some(value) => {
+            warn!(
+                "{} should be true or false, not {:?}, using {}",
+                key, value, default
+            );
+            default
+        }
+        None => default,
+    }
+}
+
+/// Splits a comma-separated list, dropping empty entries
+fn split_list(raw: Option<String>) -> Vec<String> {
+    raw.unwrap_or_default()
+        .split(',')
+        .map(|entry| entry.trim().to_string())
+        .filter(|entry| !entry.is_empty())
+        .collect()
+}
+
+/// Parses `name=value` pairs separated by commas, skipping (with a warning)
+/// entries that aren't a valid Prometheus label or clash with a metric's own labels
+fn parse_static_labels(raw: Option<String>) -> HashMap<String, String> {
+    let mut labels = HashMap::new();
+    for entry in split_list(raw) {
+        let (name, value) = match entry.split_once('=') {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => {
+                warn!(
+                    "Ignoring STATIC_LABELS entry \"{}\", expected name=value",
+                    entry
+                );
+                continue;
             }
+        };
+
+        let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !name.starts_with("__");
+        if !valid_name || value.is_empty() {
+            warn!(
+                "Ignoring STATIC_LABELS entry \"{}\", not a valid label name and value",
+                entry
+            );
+        } else if VARIABLE_LABEL_NAMES.contains(&name) {
+            warn!(
+                "Ignoring STATIC_LABELS entry \"{}\", {} is already a metric label",
+                entry, name
+            );
         } else {
-            println!("The AirTable JSON is Invalid : The JSON does not contain a 'records' key");
-            return num_records as u16;
+            labels.insert(name.to_string(), value.to_string());
         }
     }
+    labels
 }
 
-async fn fetch_pull_requests(github_api_key: Option<String>) -> Vec<PullRequest> {
-    let mut page_num = 1;
-    let mut headers = HeaderMap::new();
+/// Falls back to `default` when unset, or (with a warning) when set but empty
+fn or_default(key: &str, raw: Option<String>, default: &str) -> String {
+    match raw {
+        Some(value) if !value.trim().is_empty() => value.trim().to_string(),
+        Some(_) => {
+            warn!("{} is empty, falling back to {}", key, default);
+            default.to_string()
+        }
+        None => default.to_string(),
+    }
+}
 
-    if let Some(api_key) = &github_api_key {
-        let auth_token = format!("Bearer {}", api_key);
-        headers.insert(
-            AUTHORIZATION,
-            HeaderValue::from_str(&auth_token).expect("Invalid header value"),
-        );
-        println!("GitHub API key found");
-    } else {
-        println!("No GitHub API key found");
+fn parse_listen_addr(addr_raw: Option<String>) -> SocketAddr {
+    let default_addr: SocketAddr = "0.0.0.0:8521".parse().unwrap();
+
+    match addr_raw {
+        Some(addr_raw) => match addr_raw.parse() {
+            Ok(addr) => addr,
+            Err(e) => {
+                warn!(
+                    "Cannot parse EXPORTER_ADDR \"{}\" ({}), falling back to {}",
+                    addr_raw, e, default_addr
+                );
+                default_addr
+            }
+        },
+        None => default_addr,
     }
+}
 
-    headers.insert(
-        reqwest::header::USER_AGENT,
-        HeaderValue::from_static("Prometheus Exporter"),
-    );
+fn parse_refresh_interval(interval_raw: Option<String>) -> Duration {
+    let default_interval = 60;
+
+    match interval_raw {
+        Some(interval_raw) => match interval_raw.parse::<u64>() {
+            Ok(interval) if interval > 0 => Duration::from_secs(interval),
+            _ => {
+                warn!(
+                    "REFRESH_INTERVAL_SECS \"{}\" is not a positive integer, falling back to {}",
+                    interval_raw, default_interval
+                );
+                Duration::from_secs(default_interval)
+            }
+        },
+        None => Duration::from_secs(default_interval),
+    }
+}
 
-    let client = reqwest::Client::new();
-    let mut pull_requests: Vec<PullRequest> = vec![];
-    // let mut number_of_times = 0;
-    loop {
-        let mut url: Url =
-            Url::parse("https://api.github.com/repos/hackclub/onboard/pulls").unwrap();
-        url.query_pairs_mut().append_pair("state", "all");
-        url.query_pairs_mut().append_pair("per_page", "100");
-        url.query_pairs_mut()
-            .append_pair("page", &page_num.to_string());
-
-        println!("Fetching pull requests from {}", url);
-
-        let response = client
-            .get(url.as_str())
-            .headers(headers.clone())
-            .send()
-            .await
-            .unwrap()
-            .error_for_status()
-            .expect("No Response or GitHub API Error");
+fn parse_refresh_jitter(jitter_raw: Option<String>) -> f64 {
+    let default_jitter = 0.1;
+
+    match jitter_raw {
+        Some(jitter_raw) => match jitter_raw.parse::<f64>() {
+            Ok(jitter) if (0.0..1.0).contains(&jitter) => jitter,
+            _ => {
+                warn!(
+                    "REFRESH_JITTER \"{}\" is not a fraction in [0, 1), falling back to {}",
+                    jitter_raw, default_jitter
+                );
+                default_jitter
+            }
+        },
+        None => default_jitter,
+    }
+}
 
-        let json = response.json::<serde_json::Value>().await.unwrap();
+/// Parses increasing, positive dollar amounts, falling back to the defaults
+/// (with a warning) when any of them isn't
+fn parse_grant_buckets(buckets_raw: Option<String>) -> Vec<f64> {
+    let raw = match buckets_raw {
+        Some(raw) if !raw.trim().is_empty() => raw,
+        _ => return DEFAULT_GRANT_BUCKETS.to_vec(),
+    };
 
-        if json.as_array().map_or(false, |arr| arr.is_empty()) {
-            return pull_requests;
+    let buckets: Option<Vec<f64>> = split_list(Some(raw.clone()))
+        .iter()
+        .map(|bucket| bucket.parse::<f64>().ok().filter(|bucket| *bucket > 0.0))
+        .collect();
+    match buckets {
+        Some(buckets)
+            if !buckets.is_empty() && buckets.windows(2).all(|pair| pair[0] < pair[1]) =>
+        {
+            buckets
         }
-
-        for pull_request in json.as_array().unwrap() {
-            let pull_request =
-                PullRequest::is_merged(serde_json::from_value(pull_request.clone()).unwrap());
-            pull_requests.push(pull_request);
+        _ => {
+            warn!(
+                "GRANT_HISTOGRAM_BUCKETS \"{}\" is not a list of increasing positive amounts, \
+                 using {:?}",
+                raw, DEFAULT_GRANT_BUCKETS
+            );
+            DEFAULT_GRANT_BUCKETS.to_vec()
         }
+    }
+}
 
-        println!("Number of fetched pull requests {}.", pull_requests.len());
-        page_num += 1;
+fn parse_max_grant_dollars(max_raw: Option<String>) -> u64 {
+    let default_max = 100;
+
+    match max_raw {
+        Some(max_raw) => match max_raw.parse::<u64>() {
+            Ok(max) if max > 0 => max,
+            _ => {
+                warn!(
+                    "MAX_GRANT_DOLLARS \"{}\" is not a positive integer, falling back to {}",
+                    max_raw, default_max
+                );
+                default_max
+            }
+        },
+        None => default_max,
     }
 }
 
-fn parse_reviewer_stats(prs: Vec<PullRequest>, state: State) -> HashMap<String, u32> {
-    let mut reviewer_counts = HashMap::new();
-    for pr in prs {
-        if !pr.labels.is_empty() {
-            // if pr.labels[0].name == "Submission" || pr.labels[0].name == "Dev" {
-            if pr.labels[0].name == "Submission" {
-                match state {
-                    State::open => {
-                        if pr.state == State::open {
-                            for reviewer in pr.assignees {
-                                *reviewer_counts.entry(reviewer.login).or_insert(0) += 1;
-                            }
-                        }
-                    }
-                    State::closed => {
-                        if pr.state == State::closed {
-                            for reviewer in pr.assignees {
-                                *reviewer_counts.entry(reviewer.login).or_insert(0) += 1;
-                            }
-                        }
-                    }
-                    State::merged => {
-                        if pr.state == State::merged {
-                            for reviewer in pr.assignees {
-                                *reviewer_counts.entry(reviewer.login).or_insert(0) += 1;
-                            }
-                        }
+/// Counts `repo`'s projects, cloning it again once the cached copy is older
+/// than the TTL. With `breakdown` the projects are also walked for the
+/// `ProjectBreakdown`, in the same blocking task as the count.
+async fn count_dirs(
+    cache: &mut Option<RepoCache>,
+    repo: &OnboardRepo,
+    config: &Config,
+    breakdown: bool,
+    metrics: &GitMetrics,
+) -> anyhow::Result<(f64, Option<ProjectBreakdown>)> {
+    let cache_hit = match cache {
+        Some(cached) => cached.last_fetch.elapsed() < config.repo_cache_ttl && cached.path.is_dir(),
+        None => false,
+    };
+
+    if cache_hit {
+        info!("Using cached copy of the OnBoard repo");
+    } else {
+        // Forget the stale copy up front, so if the clone fails the next
+        // refresh tries again instead of trusting it for another TTL
+        *cache = None;
+    }
+
+    // The clone and directory walks are blocking, keep them off the runtime
+    let (repo_url, branch) = (repo.url.clone(), config.onboard_repo_branch.clone());
+    let repo_path = config.projects_path.clone();
+    let categorized = config.projects_categorized;
+    let github_token = config.github_token.clone();
+    let path = repo.dest.clone();
+    let metrics = metrics.clone();
+    let (dir_count, project_breakdown, cloned) = tokio::task::spawn_blocking(
+        move || -> anyhow::Result<(f64, Option<ProjectBreakdown>, bool)> {
+            let mut cloned = false;
+            if !cache_hit {
+                metrics.clones.inc();
+                let result = clone_projects(
+                    &repo_url,
+                    &branch,
+                    &repo_path,
+                    &path,
+                    github_token.as_deref(),
+                );
+                if result.is_err() {
+                    metrics.clone_failures.inc();
+                }
+                match result {
+                    Ok(()) => cloned = true,
+                    Err(e) if path.is_dir() => {
+                        warn!(
+                            target: "github",
+                            "Failed to clone the OnBoard repo, counting the last good copy: {:#}",
+                            e
+                        );
                     }
-                    State::any => {
-                        for reviewer in pr.assignees {
-                            *reviewer_counts.entry(reviewer.login).or_insert(0) += 1;
-                        }
+                    Err(e) => {
+                        return Err(
+                            e.context("no previous copy of the OnBoard repo to fall back to")
+                        )
                     }
                 }
-            } else {
-                println!("Pull Request {} is not a submission or dev PR", pr.number);
             }
-        }
+
+            let dir_count = count_projects(path.as_path())?;
+            // The copy just counted, not whatever PROJECTS_PATH names locally
+            let project_breakdown = breakdown.then(|| ProjectBreakdown::walk(&path, categorized));
+            Ok((dir_count, project_breakdown, cloned))
+        },
+    )
+    .await??;
+
+    if cloned {
+        *cache = Some(RepoCache {
+            last_fetch: Instant::now(),
+            path: repo.dest.clone(),
+        });
+        info!("Cloned a fresh copy of the OnBoard repo");
     }
-    reviewer_counts
+
+    Ok((dir_count, project_breakdown))
 }
 
-fn awaiting_reviews(prs: Vec<PullRequest>) -> u32 {
-    let mut awaiting_reviews = 0;
-    for pr in prs {
-        if pr.labels.is_empty() {
-            continue;
+/// Clones the projects tree next to `dest` and only swaps it in once the clone
+/// succeeded, so a failed clone leaves the last good copy alone.
+fn clone_projects(
+    repo_url: &str,
+    branch: &str,
+    repo_path: &str,
+    dest: &Path,
+    github_token: Option<&str>,
+) -> anyhow::Result<()> {
+    const CLONE_ATTEMPTS: u32 = 2;
+
+    fs::create_dir_all(CHECKOUTS_DIR)?;
+    let fresh = fresh_clone_path(dest);
+    let mut attempt = 1;
+    loop {
+        if fresh.exists() {
+            fs::remove_dir_all(&fresh)?;
         }
-
-        if pr.labels[0].name == "Submission" || pr.labels[0].name == "Dev" {
-            if pr.assignees.is_empty() && pr.requested_reviewers.is_empty() && pr.is_open() {
-                awaiting_reviews += 1;
+        match sparse_clone(repo_url, branch, repo_path, &fresh, github_token) {
+            Ok(()) => break,
+            Err(e) if attempt < CLONE_ATTEMPTS => {
+                warn!(target: "github", "Clone attempt {} failed, retrying: {:#}", attempt, e);
+                attempt += 1;
             }
+            Err(e) => return Err(e),
         }
     }
-    awaiting_reviews
+
+    if dest.exists() {
+        fs::remove_dir_all(dest)?;
+    }
+    fs::rename(&fresh, dest)?;
+    Ok(())
+}
+
+/// Where `clone_projects` puts a clone of `dest` until it's complete
+fn fresh_clone_path(dest: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.new", dest.display()))
 }
 
-impl IsOpen for PullRequest {
-    fn is_open(&self) -> bool {
-        self.state == State::open
+/// Every directory the clones of `repos` can leave behind
+fn checkout_paths(repos: &[OnboardRepo]) -> Vec<PathBuf> {
+    repos
+        .iter()
+        .flat_map(|repo| [repo.dest.clone(), fresh_clone_path(&repo.dest)])
+        .chain([clone_checkout()])
+        .collect()
+}
+
+/// Every projects tree is checked out under here, whatever PROJECTS_PATH is,
+/// so the exporter only ever replaces or deletes directories it created
+const CHECKOUTS_DIR: &str = ".onboard-checkouts";
+
+/// Scratch checkout every clone starts in, next to the projects trees so the
+/// final rename never crosses filesystems
+fn clone_checkout() -> PathBuf {
+    Path::new(CHECKOUTS_DIR).join(".clone")
+}
+
+/// Shallow, blob-less, sparse clone of just `subpath` of the repo into `dest`,
+/// so we only download the projects tree rather than the whole repo history.
+/// With a token the clone is authenticated, which gets a far higher rate limit
+/// and works for private forks.
+fn sparse_clone(
+    repo_url: &str,
+    branch: &str,
+    subpath: &str,
+    dest: &Path,
+    github_token: Option<&str>,
+) -> anyhow::Result<()> {
+    let subpath = subpath.trim_end_matches('/');
+    let checkout = clone_checkout();
+    if checkout.exists() {
+        fs::remove_dir_all(&checkout)?;
+    }
+
+    let status = git_command(repo_url, github_token)
+        .args(["clone", "--depth", "1", "--filter=blob:none", "--sparse"])
+        .args(["--branch", branch, repo_url])
+        .arg(&checkout)
+        .status()?;
+    if !status.success() {
+        anyhow::bail!("git clone of {} exited with {}", repo_url, status);
+    }
+
+    // Fetches the blobs the blob-less clone left out, so it needs the token too
+    let status = git_command(repo_url, github_token)
+        .arg("-C")
+        .arg(&checkout)
+        .args(["sparse-checkout", "set", subpath])
+        .status()?;
+    if !status.success() {
+        fs::remove_dir_all(&checkout)?;
+        anyhow::bail!("git sparse-checkout of {} exited with {}", subpath, status);
     }
+
+    // The clone itself worked, the repo just doesn't have the subtree (any
+    // more), which is a count of 0 rather than a failure
+    let result = if checkout.join(subpath).is_dir() {
+        fs::rename(checkout.join(subpath), dest)
+    } else {
+        warn!(
+            target: "github",
+            "{} has no {} directory, counting 0 projects",
+            repo_url,
+            subpath
+        );
+        fs::create_dir_all(dest)
+    };
+    fs::remove_dir_all(&checkout)?;
+    result?;
+    Ok(())
 }
 
-trait IsOpen {
-    fn is_open(&self) -> bool;
+/// A git command for `repo_url` that authenticates with `github_token`, if
+/// any. The token is passed as an `Authorization` header through git's
+/// environment config, so it never shows up in the process list or the
+/// clone's `.git/config`. It's only ever sent to github.com, a repo hosted
+/// anywhere else is fetched anonymously.
+fn git_command(repo_url: &str, github_token: Option<&str>) -> Command {
+    let mut command = Command::new("git");
+    // Fail instead of waiting on a credentials prompt nobody will answer
+    command.env("GIT_TERMINAL_PROMPT", "0");
+    let on_github = Url::parse(repo_url)
+        .ok()
+        .is_some_and(|url| url.scheme() == "https" && url.host_str() == Some("github.com"));
+    if let Some(token) = github_token.filter(|_| on_github) {
+        let credentials = base64(format!("x-access-token:{}", token).as_bytes());
+        command
+            .env("GIT_CONFIG_COUNT", "1")
+            // Scoped to the host too, in case git follows a redirect elsewhere
+            .env("GIT_CONFIG_KEY_0", "http.https://github.com/.extraHeader")
+            .env(
+                "GIT_CONFIG_VALUE_0",
+                format!("Authorization: Basic {}", credentials),
+            );
+    }
+    command
 }
 
-trait IsMerged {
-    fn is_merged(self) -> Self;
+/// Padded standard base64, for the `Authorization: Basic` header git sends
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let byte = |i: usize| u32::from(chunk.get(i).copied().unwrap_or(0));
+        let triple = (byte(0) << 16) | (byte(1) << 8) | byte(2);
+        // A chunk of n bytes makes n + 1 characters, padded to 4 with '='
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[((triple >> (18 - 6 * i)) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Everything derived from walking a fresh copy of the projects directory,
+/// besides the plain count
+#[derive(Default)]
+struct ProjectBreakdown {
+    categories: HashMap<String, f64>,
+    by_readme: HashMap<&'static str, f64>,
+    requested_dollars: Option<(f64, i64)>,
 }
 
-impl IsMerged for PullRequest {
-    fn is_merged(mut self) -> Self {
-        if self.merged_at.is_some() {
-            self.state = State::merged;
+impl ProjectBreakdown {
+    fn walk(checkout: &Path, categorized: bool) -> ProjectBreakdown {
+        ProjectBreakdown {
+            categories: if categorized {
+                count_project_categories(checkout)
+            } else {
+                HashMap::new()
+            },
+            by_readme: count_projects_by_readme(checkout),
+            requested_dollars: sum_requested_dollars(checkout),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_listen_addr_falls_back_to_the_default() {
+        let default_addr: SocketAddr = "0.0.0.0:8521".parse().unwrap();
+        assert_eq!(parse_listen_addr(None), default_addr);
+        assert_eq!(
+            parse_listen_addr(Some("127.0.0.1:9000".to_string())),
+            "127.0.0.1:9000".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            parse_listen_addr(Some("localhost".to_string())),
+            default_addr
+        );
+    }
+
+    #[test]
+    fn parse_max_grant_dollars_wants_a_positive_integer() {
+        assert_eq!(parse_max_grant_dollars(None), 100);
+        assert_eq!(parse_max_grant_dollars(Some("250".to_string())), 250);
+        assert_eq!(parse_max_grant_dollars(Some("0".to_string())), 100);
+        assert_eq!(parse_max_grant_dollars(Some("-5".to_string())), 100);
+        assert_eq!(parse_max_grant_dollars(Some("lots".to_string())), 100);
+    }
+
+    #[test]
+    fn parse_static_labels_skips_bad_and_taken_names() {
+        let labels = parse_static_labels(Some(
+            "env=prod, region = us ,bad,org=onboard,9x=y,__x=y,empty=".to_string(),
+        ));
+        assert_eq!(
+            labels,
+            HashMap::from([
+                ("env".to_string(), "prod".to_string()),
+                ("region".to_string(), "us".to_string()),
+            ])
+        );
+        assert!(parse_static_labels(None).is_empty());
+    }
+
+    #[test]
+    fn parse_grant_buckets_wants_increasing_positive_amounts() {
+        assert_eq!(parse_grant_buckets(None), DEFAULT_GRANT_BUCKETS);
+        assert_eq!(
+            parse_grant_buckets(Some("10, 50,100".to_string())),
+            vec![10.0, 50.0, 100.0]
+        );
+        for raw in ["", "50,10", "10,10", "0,10", "10,abc"] {
+            assert_eq!(
+                parse_grant_buckets(Some(raw.to_string())),
+                DEFAULT_GRANT_BUCKETS,
+                "{:?} should fall back to the defaults",
+                raw
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_gzip_honours_q_zero() {
+        assert!(accepts_gzip("gzip"));
+        assert!(accepts_gzip("deflate, GZIP;q=0.5"));
+        assert!(accepts_gzip("*"));
+        assert!(!accepts_gzip(""));
+        assert!(!accepts_gzip("identity"));
+        assert!(!accepts_gzip("gzip;q=0"));
+        assert!(!accepts_gzip("br, gzip; q=0.0"));
+    }
+
+    #[test]
+    fn parse_projects_path_stays_inside_the_repo() {
+        assert_eq!(parse_projects_path(None).unwrap(), "projects");
+        assert_eq!(
+            parse_projects_path(Some("src/projects/".to_string())).unwrap(),
+            "src/projects"
+        );
+        assert_eq!(
+            parse_projects_path(Some("./projects".to_string())).unwrap(),
+            "projects"
+        );
+        for raw in [
+            "",
+            " ",
+            ".",
+            "./",
+            "..",
+            "../projects",
+            "src/../..",
+            "/projects",
+        ] {
+            assert!(
+                parse_projects_path(Some(raw.to_string())).is_err(),
+                "{:?} should be refused",
+                raw
+            );
+        }
+    }
+
+    #[test]
+    fn onboard_repos_get_their_own_checkout() {
+        let repo = OnboardRepo::new("https://github.com/hackclub/OnBoard".to_string()).unwrap();
+        assert_eq!(repo.label, "hackclub/OnBoard");
+        assert_eq!(repo.dest, Path::new(".onboard-checkouts/hackclub_OnBoard"));
+
+        let fork = OnboardRepo::new("https://github.com/you/OnBoard.git".to_string()).unwrap();
+        assert_eq!(fork.dest, Path::new(".onboard-checkouts/you_OnBoard"));
+    }
+
+    #[test]
+    fn checkout_paths_stay_under_the_checkouts_dir() {
+        let repos = [
+            OnboardRepo::new("https://github.com/hackclub/OnBoard".to_string()).unwrap(),
+            OnboardRepo::new("https://github.com/you/OnBoard".to_string()).unwrap(),
+        ];
+        let paths = checkout_paths(&repos);
+        assert_eq!(paths.len(), 5);
+        for path in paths {
+            assert!(
+                path.starts_with(CHECKOUTS_DIR),
+                "{} is outside",
+                path.display()
+            );
+            assert_ne!(path, Path::new(CHECKOUTS_DIR));
         }
-        self
+    }
+
+    #[tokio::test]
+    async fn build_client_sends_the_user_agent() {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let addr = server.server_addr().to_ip().unwrap();
+        let received = thread::spawn(move || {
+            let request = server.recv().unwrap();
+            let user_agent = request
+                .headers()
+                .iter()
+                .find(|header| header.field.equiv("User-Agent"))
+                .map(|header| header.value.to_string());
+            let _ = request.respond(tiny_http::Response::empty(204));
+            user_agent
+        });
+        let config = Config::from_args(Args::parse_from(["OnboardGrant"])).unwrap();
+
+        build_client(&config)
+            .get(format!("http://{}/", addr))
+            .send()
+            .await
+            .unwrap();
+
+        let expected = format!("onboard-grant-aggregator/{}", env!("CARGO_PKG_VERSION"));
+        assert_eq!(received.join().unwrap(), Some(expected));
+    }
+
+    #[test]
+    fn git_command_only_sends_the_token_to_github() {
+        let header = |repo_url: &str| {
+            git_command(repo_url, Some("secret"))
+                .get_envs()
+                .find(|(key, _)| *key == "GIT_CONFIG_VALUE_0")
+                .and_then(|(_, value)| value)
+                .map(|value| value.to_string_lossy().into_owned())
+        };
+        assert_eq!(
+            header("https://github.com/hackclub/OnBoard"),
+            Some(format!(
+                "Authorization: Basic {}",
+                base64(b"x-access-token:secret")
+            ))
+        );
+        assert_eq!(header("https://gitlab.com/hackclub/OnBoard"), None);
+        assert_eq!(
+            header("https://github.com.example.org/hackclub/OnBoard"),
+            None
+        );
+        assert_eq!(header("http://github.com/hackclub/OnBoard"), None);
+        assert_eq!(header("git@github.com:hackclub/OnBoard.git"), None);
+    }
+
+    #[test]
+    fn base64_pads_to_whole_quads() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(
+            base64(b"x-access-token:ghp_abc"),
+            "eC1hY2Nlc3MtdG9rZW46Z2hwX2FiYw=="
+        );
     }
 }
@@ -0,0 +1,180 @@
+mod common;
+
+use common::MockServer;
+use reqwest::Client;
+use serde_json::json;
+use OnboardGrant::*;
+
+const APPROVED: &str = "/appTest/Submissions?pageSize=100&view=Approved";
+
+fn config(server: &MockServer) -> AirtableConfig {
+    AirtableConfig {
+        api_url: server.url.clone(),
+        api_key: Some("key".to_string()),
+        base_id: "appTest".to_string(),
+        table: "Submissions".to_string(),
+        views: vec!["Approved".to_string()],
+        filter_formula: None,
+        submitted_field: None,
+        approved_field: None,
+        max_retries: 0,
+        dump_json: false,
+    }
+}
+
+fn record(id: &str) -> serde_json::Value {
+    json!({ "id": id, "createdTime": "2024-03-01T10:00:00.000Z", "fields": {} })
+}
+
+#[tokio::test]
+async fn airtable_records_follows_the_offset() {
+    let server = MockServer::start();
+    server.respond(
+        APPROVED,
+        200,
+        json!({ "records": [record("rec1"), record("rec2")], "offset": "next" }),
+    );
+    server.respond(
+        &format!("{}&offset=next", APPROVED),
+        200,
+        json!({ "records": [record("rec3")] }),
+    );
+    let metrics = common::metrics();
+
+    let counts =
+        airtable_records_by_view(&Client::new(), &config(&server), &metrics.airtable).await;
+
+    assert_eq!(counts.len(), 1);
+    assert_eq!(counts[0].0, "Approved");
+    assert_eq!(*counts[0].1.as_ref().unwrap(), 3);
+}
+
+#[tokio::test]
+async fn airtable_records_fails_on_a_refused_key() {
+    let server = MockServer::start();
+    server.respond(APPROVED, 401, json!({ "error": "AUTHENTICATION_REQUIRED" }));
+    let metrics = common::metrics();
+
+    let counts =
+        airtable_records_by_view(&Client::new(), &config(&server), &metrics.airtable).await;
+
+    assert!(counts[0].1.is_err());
+    assert_eq!(metrics.airtable.auth_errors.get(), 1);
+}
+
+#[tokio::test]
+async fn airtable_records_fails_on_a_page_that_does_not_parse() {
+    let server = MockServer::start();
+    server.respond(
+        APPROVED,
+        200,
+        json!({ "records": [record("rec1")], "offset": "next" }),
+    );
+    server.respond(
+        &format!("{}&offset=next", APPROVED),
+        200,
+        json!({ "records": "unexpected" }),
+    );
+    let metrics = common::metrics();
+
+    let counts =
+        airtable_records_by_view(&Client::new(), &config(&server), &metrics.airtable).await;
+
+    assert!(counts[0].1.is_err());
+}
+
+#[tokio::test]
+async fn airtable_records_fails_on_an_unknown_view_and_keeps_the_last_count() {
+    let server = MockServer::start();
+    server.respond(APPROVED, 200, json!({ "records": [record("rec1")] }));
+    let metrics = common::metrics();
+    let mut state = MetricsState::new();
+    let query = AirtableQuery {
+        view: Some("Approved"),
+        filter_formula: None,
+        fields: &[],
+    };
+    let client = Client::new();
+    let config = config(&server);
+
+    // Handled the way main does: fresh on success, stale on failure
+    let records = airtable_records(&client, &config, &metrics.airtable, &query)
+        .await
+        .unwrap();
+    state.set_fresh(
+        &metrics.airtable_records,
+        &metrics.airtable_records_stale,
+        &["Approved", ""],
+        records.len() as f64,
+    );
+
+    server.respond(
+        APPROVED,
+        422,
+        json!({ "error": { "type": "VIEW_NAME_NOT_FOUND" } }),
+    );
+    let result = airtable_records(&client, &config, &metrics.airtable, &query).await;
+    let error = result.err().expect("an unknown view is an error");
+    assert!(error.to_string().contains("VIEW_NAME_NOT_FOUND"));
+    state.set_stale(
+        &metrics.airtable_records,
+        &metrics.airtable_records_stale,
+        &["Approved", ""],
+    );
+
+    let labels = ["Approved", ""];
+    assert_eq!(metrics.airtable_records.with_label_values(&labels).get(), 1);
+    assert_eq!(
+        metrics
+            .airtable_records_stale
+            .with_label_values(&labels)
+            .get(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn airtable_records_by_view_counts_the_views_that_answered() {
+    let server = MockServer::start();
+    server.respond(APPROVED, 200, json!({ "records": [record("rec1")] }));
+    server.respond(
+        "/appTest/Submissions?pageSize=100&view=Pending",
+        422,
+        json!({ "error": { "type": "VIEW_NAME_NOT_FOUND" } }),
+    );
+    let mut config = config(&server);
+    config.views = vec!["Approved".to_string(), "Pending".to_string()];
+    let metrics = common::metrics();
+
+    let counts = airtable_records_by_view(&Client::new(), &config, &metrics.airtable).await;
+
+    assert_eq!(*counts[0].1.as_ref().unwrap(), 1);
+    assert!(counts[1].1.is_err());
+    assert_eq!(metrics.airtable.views_configured.get(), 2);
+    assert_eq!(metrics.airtable.views_fetched.get(), 1);
+}
+
+#[tokio::test]
+async fn airtable_verifications_goes_past_u16() {
+    let server = MockServer::start();
+    // Airtable itself pages at 100, one big page keeps the test quick
+    let records: Vec<serde_json::Value> = (0..70_000)
+        .map(|index| record(&format!("rec{}", index)))
+        .collect();
+    server.respond(APPROVED, 200, json!({ "records": records }));
+    let metrics = common::metrics();
+
+    let count = airtable_verifications(
+        &Client::new(),
+        &config(&server),
+        &metrics.airtable,
+        &AirtableQuery {
+            view: Some("Approved"),
+            filter_formula: None,
+            fields: &[],
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(count, 70_000);
+}
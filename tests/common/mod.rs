@@ -0,0 +1,82 @@
+//! A stand-in for the upstream APIs: serves canned JSON on a random local
+//! port so the fetchers can be pointed at it through their `*_API_URL`.
+
+// Every test crate includes this, none of them uses all of it
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use prometheus_exporter::prometheus::Registry;
+use serde_json::Value;
+use tiny_http::{Header, Response, Server};
+use OnboardGrant::{register_metrics, Metrics, DEFAULT_GRANT_BUCKETS};
+
+type Routes = Arc<Mutex<HashMap<String, (u16, Value)>>>;
+
+pub struct MockServer {
+    pub url: String,
+    routes: Routes,
+    hits: Arc<AtomicUsize>,
+}
+
+impl MockServer {
+    pub fn start() -> MockServer {
+        let server = Server::http("127.0.0.1:0").expect("Cannot start mock server");
+        let addr = server
+            .server_addr()
+            .to_ip()
+            .expect("Mock server listens on TCP");
+        let routes: Routes = Arc::default();
+        let hits = Arc::new(AtomicUsize::new(0));
+
+        let (served_routes, served_hits) = (routes.clone(), hits.clone());
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                served_hits.fetch_add(1, Ordering::SeqCst);
+                // The longest matching route wins, so a specific page can be
+                // answered differently from the rest of an endpoint
+                let (status, body) = served_routes
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|(route, _)| request.url().starts_with(route.as_str()))
+                    .max_by_key(|(route, _)| route.len())
+                    .map(|(_, answer)| answer.clone())
+                    .unwrap_or((404, serde_json::json!({ "error": "no such route" })));
+                let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+                let response = Response::from_string(body.to_string())
+                    .with_status_code(status)
+                    .with_header(content_type);
+                let _ = request.respond(response);
+            }
+        });
+
+        MockServer {
+            url: format!("http://{}", addr),
+            routes,
+            hits,
+        }
+    }
+
+    /// Answers every request whose path and query start with `route`,
+    /// replacing whatever it answered before
+    pub fn respond(&self, route: &str, status: u16, body: Value) {
+        self.routes
+            .lock()
+            .unwrap()
+            .insert(route.to_string(), (status, body));
+    }
+
+    /// Number of requests served so far
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::SeqCst)
+    }
+}
+
+/// A full set of metrics in a registry of its own, so tests don't collide
+pub fn metrics() -> Metrics {
+    register_metrics(&Registry::new(), &HashMap::new(), DEFAULT_GRANT_BUCKETS)
+}
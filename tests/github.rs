@@ -0,0 +1,119 @@
+mod common;
+
+use common::MockServer;
+use reqwest::Client;
+use serde_json::{json, Value};
+use OnboardGrant::*;
+
+const PULLS: &str = "/repos/hackclub/onboard/pulls";
+
+fn pull_request(number: u32, state: &str, merged_at: Option<&str>) -> Value {
+    json!({
+        "number": number,
+        "assignees": [],
+        "labels": [{ "name": "Submission" }],
+        "requested_reviewers": [],
+        "state": state,
+        "merged_at": merged_at,
+    })
+}
+
+#[tokio::test]
+async fn fetch_pull_requests_reads_every_page() {
+    let server = MockServer::start();
+    server.respond(PULLS, 200, json!([]));
+    server.respond(
+        &format!("{}?state=all&per_page=100&page=1", PULLS),
+        200,
+        json!([
+            pull_request(1, "open", None),
+            pull_request(2, "closed", Some("2024-03-01T10:00:00Z")),
+            pull_request(3, "closed", None),
+        ]),
+    );
+    let metrics = common::metrics();
+
+    let prs = fetch_pull_requests(
+        &Client::new(),
+        &server.url,
+        None,
+        0,
+        &metrics.github_rate_limit_remaining,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(prs.len(), 3);
+    assert_eq!(count_onboard_prs(&prs, None), (1, 1));
+    assert_eq!(awaiting_reviews(prs), 1);
+}
+
+#[tokio::test]
+async fn fetch_pull_requests_fails_instead_of_panicking() {
+    let server = MockServer::start();
+    server.respond(PULLS, 401, json!({ "message": "Bad credentials" }));
+    let metrics = common::metrics();
+
+    let prs = fetch_pull_requests(
+        &Client::new(),
+        &server.url,
+        Some("bogus".to_string()),
+        0,
+        &metrics.github_rate_limit_remaining,
+    )
+    .await;
+
+    assert!(prs.is_err());
+}
+
+#[tokio::test]
+async fn github_pr_stats_counts_merges_and_reviews() {
+    let server = MockServer::start();
+    server.respond(
+        "/graphql",
+        200,
+        json!({ "data": { "repository": { "pullRequests": {
+            "pageInfo": { "hasNextPage": false, "endCursor": null },
+            "nodes": [
+                { "mergedBy": { "login": "alice" },
+                  "reviews": { "nodes": [{ "author": { "login": "bob" } }] } },
+                { "mergedBy": { "login": "alice" },
+                  "reviews": { "nodes": [{ "author": { "login": "alice" } }] } },
+            ],
+        } } } }),
+    );
+    let metrics = common::metrics();
+
+    let stats = github_pr_stats(
+        &Client::new(),
+        &server.url,
+        Some("token".to_string()),
+        &metrics.github_rate_limit_remaining,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(stats["alice"], (2, 1));
+    assert_eq!(stats["bob"], (0, 1));
+}
+
+#[tokio::test]
+async fn github_pr_stats_gives_up_on_graphql_errors() {
+    let server = MockServer::start();
+    server.respond(
+        "/graphql",
+        200,
+        json!({ "data": null, "errors": [{ "message": "Something went wrong" }] }),
+    );
+    let metrics = common::metrics();
+
+    let stats = github_pr_stats(
+        &Client::new(),
+        &server.url,
+        Some("token".to_string()),
+        &metrics.github_rate_limit_remaining,
+    )
+    .await;
+
+    assert!(stats.is_none());
+}
@@ -0,0 +1,241 @@
+mod common;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use common::MockServer;
+use reqwest::Client;
+use serde_json::{json, Value};
+use OnboardGrant::*;
+
+const TRANSFERS: &str = "/organizations/onboard/transfers/";
+
+fn config(server: &MockServer) -> HcbConfig {
+    HcbConfig {
+        api_url: server.url.clone(),
+        orgs: vec!["onboard".to_string()],
+        max_grant_dollars: 100,
+        page_size: 100,
+        include_types: Vec::new(),
+        exclude_types: Vec::new(),
+        cache_ttl: Duration::ZERO,
+        max_retries: 0,
+        streaming: false,
+        dump_json: false,
+    }
+}
+
+/// The route of one page of transfers, as `walk_hcb_pages` asks for it
+fn page(config: &HcbConfig, page: u32) -> String {
+    format!(
+        "{}?per_page={}&expand=transaction&page={}",
+        TRANSFERS, config.page_size, page
+    )
+}
+
+fn transfer(id: &str, amount_cents: u64, type_: &str) -> Value {
+    json!({
+        "id": id,
+        "object": "transfer",
+        "href": format!("https://hcb.hackclub.com/api/v3/transfers/{}", id),
+        "transaction": {
+            "id": format!("txn_{}", id),
+            "object": "transaction",
+            "href": format!("https://hcb.hackclub.com/api/v3/transactions/txn_{}", id),
+            "amount_cents": amount_cents,
+            "memo": "OnBoard grant [Arcade]",
+            "type": type_,
+        },
+        "amount_cents": amount_cents,
+        "date": "2024-03-01",
+    })
+}
+
+#[tokio::test]
+async fn fetch_hcb_filters_by_type_and_amount() {
+    let server = MockServer::start();
+    server.respond(
+        TRANSFERS,
+        200,
+        json!([
+            transfer("xfr_1", 5000, "transfer"),
+            transfer("xfr_2", 2500, "transfer"),
+            transfer("xfr_3", 50000, "transfer"),
+            transfer("xfr_4", 1000, "fee"),
+        ]),
+    );
+    let mut config = config(&server);
+    config.exclude_types = vec!["fee".to_string()];
+    let metrics = common::metrics();
+
+    let transfers = fetch_hcb(&Client::new(), &config, "onboard", &metrics.hcb)
+        .await
+        .unwrap();
+
+    let ids: Vec<&str> = transfers
+        .iter()
+        .map(|transfer| transfer.id.as_str())
+        .collect();
+    assert_eq!(ids, ["xfr_1", "xfr_2"]);
+    assert_eq!(avg_grant(&Ok(transfers)), 37.5);
+    let filtered_out = &metrics.hcb.filtered_out;
+    assert_eq!(
+        filtered_out.with_label_values(&["onboard", "type"]).get(),
+        1
+    );
+    assert_eq!(
+        filtered_out.with_label_values(&["onboard", "amount"]).get(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn fetch_hcb_fails_on_an_error_object() {
+    let server = MockServer::start();
+    server.respond(TRANSFERS, 200, json!({ "error": "not found" }));
+    let metrics = common::metrics();
+
+    let result = fetch_hcb(&Client::new(), &config(&server), "onboard", &metrics.hcb).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn fetch_hcb_fails_on_a_server_error() {
+    let server = MockServer::start();
+    server.respond(TRANSFERS, 503, json!({ "error": "maintenance" }));
+    let metrics = common::metrics();
+
+    let result = fetch_hcb(&Client::new(), &config(&server), "onboard", &metrics.hcb).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn fetch_hcb_orgs_picks_up_new_transfers_after_the_ttl() {
+    let server = MockServer::start();
+    server.respond(TRANSFERS, 200, json!([transfer("xfr_1", 5000, "transfer")]));
+    let mut config = config(&server);
+    config.cache_ttl = Duration::from_millis(200);
+    let metrics = common::metrics();
+    let client = Client::new();
+    let mut cache = HashMap::new();
+
+    let fetched = fetch_hcb_orgs(&client, &config, &metrics.hcb, &mut cache).await;
+    assert_eq!(count_transfers(&fetched[0].1), 1);
+    assert!(fetched[0].2);
+
+    // Within the TTL the cached transfers are served and HCB isn't asked again
+    server.respond(
+        TRANSFERS,
+        200,
+        json!([
+            transfer("xfr_1", 5000, "transfer"),
+            transfer("xfr_2", 2500, "transfer"),
+        ]),
+    );
+    let hits = server.hits();
+    let cached = fetch_hcb_orgs(&client, &config, &metrics.hcb, &mut cache).await;
+    assert_eq!(count_transfers(&cached[0].1), 1);
+    assert!(!cached[0].2);
+    assert_eq!(server.hits(), hits);
+
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    let refetched = fetch_hcb_orgs(&client, &config, &metrics.hcb, &mut cache).await;
+    assert_eq!(count_transfers(&refetched[0].1), 2);
+    assert!(refetched[0].2);
+}
+
+#[tokio::test]
+async fn fetch_hcb_stops_at_the_empty_page_after_a_full_one() {
+    let server = MockServer::start();
+    let mut config = config(&server);
+    config.page_size = 2;
+    server.respond(
+        &page(&config, 1),
+        200,
+        json!([
+            transfer("xfr_1", 5000, "transfer"),
+            transfer("xfr_2", 2500, "transfer"),
+        ]),
+    );
+    server.respond(&page(&config, 2), 200, json!([]));
+    let metrics = common::metrics();
+
+    let transfers = fetch_hcb(&Client::new(), &config, "onboard", &metrics.hcb)
+        .await
+        .unwrap();
+    assert_eq!(transfers.len(), 2);
+    assert_eq!(server.hits(), 2);
+}
+
+#[tokio::test]
+async fn fetch_hcb_stops_after_a_short_page() {
+    let server = MockServer::start();
+    let mut config = config(&server);
+    config.page_size = 2;
+    server.respond(
+        &page(&config, 1),
+        200,
+        json!([
+            transfer("xfr_1", 5000, "transfer"),
+            transfer("xfr_2", 2500, "transfer"),
+        ]),
+    );
+    server.respond(
+        &page(&config, 2),
+        200,
+        json!([transfer("xfr_3", 1000, "transfer")]),
+    );
+    // Asking for it would fail the fetch
+    server.respond(&page(&config, 3), 503, json!({ "error": "no such page" }));
+    let metrics = common::metrics();
+
+    let transfers = fetch_hcb(&Client::new(), &config, "onboard", &metrics.hcb)
+        .await
+        .unwrap();
+    assert_eq!(transfers.len(), 3);
+    assert_eq!(server.hits(), 2);
+}
+
+#[tokio::test]
+async fn fetch_hcb_counts_a_transfer_repeated_across_pages_once() {
+    let server = MockServer::start();
+    let mut config = config(&server);
+    config.page_size = 2;
+    // xfr_2 slid onto the second page while the first one was being read
+    server.respond(
+        &page(&config, 1),
+        200,
+        json!([
+            transfer("xfr_1", 5000, "transfer"),
+            transfer("xfr_2", 2500, "transfer"),
+        ]),
+    );
+    server.respond(
+        &page(&config, 2),
+        200,
+        json!([
+            transfer("xfr_2", 2500, "transfer"),
+            transfer("xfr_3", 1000, "transfer"),
+        ]),
+    );
+    server.respond(&page(&config, 3), 200, json!([]));
+    let metrics = common::metrics();
+
+    let transfers = fetch_hcb(&Client::new(), &config, "onboard", &metrics.hcb)
+        .await
+        .unwrap();
+    let ids: Vec<&str> = transfers
+        .iter()
+        .map(|transfer| transfer.id.as_str())
+        .collect();
+    assert_eq!(ids, ["xfr_1", "xfr_2", "xfr_3"]);
+    assert_eq!(
+        metrics
+            .hcb
+            .raw_transfers
+            .with_label_values(&["onboard"])
+            .get(),
+        3
+    );
+    assert_eq!(count_transfers(&Ok(transfers)), 3);
+}
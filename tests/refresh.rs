@@ -0,0 +1,115 @@
+//! Runs the exporter itself against a mock HCB and checks that its gauges
+//! follow the upstream from one refresh to the next.
+
+mod common;
+
+use std::net::TcpListener;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use common::MockServer;
+use serde_json::{json, Value};
+
+const TRANSFERS: &str = "/organizations/onboard/transfers/";
+
+/// Kills the exporter even when an assertion fails halfway
+struct Exporter(Child);
+
+impl Drop for Exporter {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn free_addr() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+fn transfer(id: &str, amount_cents: u64) -> Value {
+    json!({
+        "id": id,
+        "object": "transfer",
+        "href": format!("https://hcb.hackclub.com/api/v3/transfers/{}", id),
+        "transaction": {
+            "id": format!("txn_{}", id),
+            "object": "transaction",
+            "href": format!("https://hcb.hackclub.com/api/v3/transactions/txn_{}", id),
+            "amount_cents": amount_cents,
+            "memo": "OnBoard grant",
+            "type": "transfer",
+        },
+        "amount_cents": amount_cents,
+        "date": "2024-03-01",
+    })
+}
+
+/// The value of `transfers_count{org="onboard", period="all"}`, if exported yet
+async fn transfers_count(client: &reqwest::Client, addr: &str) -> Option<f64> {
+    let body = client
+        .get(format!("http://{}/metrics", addr))
+        .send()
+        .await
+        .ok()?
+        .text()
+        .await
+        .ok()?;
+    body.lines()
+        .filter(|line| line.starts_with("transfers_count{"))
+        .find(|line| line.contains(r#"org="onboard""#) && line.contains(r#"period="all""#))
+        .and_then(|line| line.rsplit(' ').next())
+        .and_then(|value| value.parse().ok())
+}
+
+/// Polls `/metrics` until `transfers_count` reads `expected`, panicking after a while
+async fn wait_for_transfers_count(client: &reqwest::Client, addr: &str, expected: f64) {
+    let deadline = Instant::now() + Duration::from_secs(20);
+    loop {
+        let count = transfers_count(client, addr).await;
+        if count == Some(expected) {
+            return;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "transfers_count stayed at {:?}, expected {}",
+            count,
+            expected
+        );
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+}
+
+#[tokio::test]
+async fn transfers_count_follows_hcb_across_refreshes() {
+    let server = MockServer::start();
+    server.respond(TRANSFERS, 200, json!([transfer("xfr_1", 5000)]));
+
+    let addr = free_addr();
+    let _exporter = Exporter(
+        Command::new(env!("CARGO_BIN_EXE_OnboardGrant"))
+            .env("EXPORTER_ADDR", &addr)
+            .env("HEALTH_ADDR", free_addr())
+            .env("HCB_API_URL", &server.url)
+            .env("HCB_ORGS", "onboard")
+            .env("HCB_CACHE_TTL_SECS", "0")
+            .env("REFRESH_INTERVAL_SECS", "1")
+            .env("REFRESH_JITTER", "0")
+            .env("ENABLE_GITHUB", "false")
+            .env("ENABLE_AIRTABLE", "false")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Cannot start the exporter"),
+    );
+    let client = reqwest::Client::new();
+
+    wait_for_transfers_count(&client, &addr, 1.0).await;
+
+    server.respond(
+        TRANSFERS,
+        200,
+        json!([transfer("xfr_1", 5000), transfer("xfr_2", 2500)]),
+    );
+    wait_for_transfers_count(&client, &addr, 2.0).await;
+}